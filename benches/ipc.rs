@@ -0,0 +1,171 @@
+//! How many ipc calls balancing takes, on made up trees of a few sizes. Run
+//! with `cargo bench`. Fails if the calls grow beyond one fetch of the tree
+//! per batch of resizes, plus the first. Also times balancing with `--jobs`
+//! against balancing on a single thread, with every call taking as long as
+//! a round trip to sway might.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use swayipc::{Fallible, Node};
+use sway_balance_workspace::{balance_parallel, balance_with, AppError, Options};
+use sway_balance_workspace::provider::{MockProvider, TreeProvider};
+
+
+/// Counts the calls going through to the mock
+struct Counting {
+    inner: MockProvider,
+    get_tree: usize,
+    run_command: usize,
+}
+
+impl TreeProvider for Counting {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        self.get_tree += 1;
+        self.inner.get_tree()
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        self.run_command += 1;
+        self.inner.run_command(cmds)
+    }
+}
+
+/// How long a call to sway is taken to take, about what a round trip over
+/// the socket with a tree of a few windows does
+const LATENCY: Duration = Duration::from_millis(1);
+
+/// A mock shared by the threads of `balance_parallel`, every one of which
+/// has a connection of its own. The calls wait out `LATENCY` first, without
+/// holding on to the mock, like they would on a connection each.
+struct Slow<'a>(&'a Mutex<MockProvider>);
+
+impl TreeProvider for Slow<'_> {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        std::thread::sleep(LATENCY);
+        self.0.lock().unwrap().get_tree()
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        std::thread::sleep(LATENCY);
+        self.0.lock().unwrap().run_command(cmds)
+    }
+}
+
+/// How long balancing `tree` on `jobs` threads takes
+fn time_jobs(tree: &Node, jobs: usize) -> Duration {
+    let mock = Mutex::new(MockProvider::new(tree.clone()));
+    let connect = || Ok::<_, AppError>(Slow(&mock));
+
+    let started = Instant::now();
+    balance_parallel(connect, tree, &Options::default(), jobs).unwrap();
+    started.elapsed()
+}
+
+
+fn rect(x: i32, y: i32, width: i32, height: i32) -> Value {
+    json!({ "x": x, "y": y, "width": width, "height": height })
+}
+
+/// A node as `swaymsg -t get_tree` prints it, with the fields swayipc needs
+fn node(id: &mut i64, kind: &str, layout: &str, r: (i32, i32, i32, i32),
+    nodes: Vec<Value>) -> Value
+{
+    *id += 1;
+    let zero = rect(0, 0, 0, 0);
+    json!({
+        "id": *id, "name": null, "type": kind, "border": "none",
+        "current_border_width": 0, "layout": layout, "percent": null,
+        "rect": rect(r.0, r.1, r.2, r.3), "window_rect": zero,
+        "deco_rect": zero, "geometry": zero, "urgent": false,
+        "focused": false, "focus": [], "nodes": nodes, "floating_nodes": [],
+        "sticky": false, "marks": [],
+    })
+}
+
+/// A split container in `r` with `leaves` windows below it, `levels` deep.
+/// Every level splits in two along the other axis than the one above, and
+/// sizes start out uneven, growing with the index of the child.
+fn container(id: &mut i64, kind: &str, leaves: usize, levels: usize, 
+    horizontal: bool, r: (i32, i32, i32, i32)) -> Value
+{
+    let n = if levels == 0 { leaves } else { 2.min(leaves) };
+    let total = (n * (n + 1) / 2) as i32;
+    let (mut pos, len) = if horizontal { (r.0, r.2) } else { (r.1, r.3) };
+
+    let mut nodes = Vec::new();
+    for i in 0..n {
+        let size = match i == n - 1 {
+            true => if horizontal { r.0 + r.2 - pos } else { r.1 + r.3 - pos },
+            false => len * (i as i32 + 1) / total,
+        };
+        let child = match horizontal {
+            true => (pos, r.1, size, r.3),
+            false => (r.0, pos, r.2, size),
+        };
+        pos += size;
+
+        let share = leaves / n + usize::from(i < leaves % n);
+        nodes.push(match levels {
+            0 => node(id, "con", "none", child, vec![]),
+            _ => container(id, "con", share, levels - 1, !horizontal, child),
+        });
+    }
+
+    let layout = if horizontal { "splith" } else { "splitv" };
+    node(id, kind, layout, r, nodes)
+}
+
+
+fn main() {
+    let shapes = [(2, 0), (8, 0), (8, 2), (32, 0), (32, 3)];
+
+    println!("{:>6} {:>6} {:>9} {:>12} {:>8} {:>8}", 
+        "leaves", "levels", "get_tree", "run_command", "batches", "us");
+
+    for (leaves, levels) in shapes {
+        let mut id = 0;
+        let workspace = container(&mut id, "workspace", leaves, levels, true,
+            (0, 0, 1920, 1080));
+        let tree: Node = serde_json::from_value(workspace).unwrap();
+
+        let mut conn = Counting { 
+            inner: MockProvider::new(tree.clone()), 
+            get_tree: 0, 
+            run_command: 0,
+        };
+        let started = Instant::now();
+        let report = balance_with(&mut conn, &tree, &Options::default())
+            .unwrap();
+        let elapsed = started.elapsed().as_micros();
+
+        let batches: usize = report.containers.iter().map(|c| c.iterations).sum();
+        println!("{leaves:>6} {levels:>6} {:>9} {:>12} {batches:>8} {elapsed:>8}",
+            conn.get_tree, conn.run_command);
+
+        assert!(conn.get_tree <= batches + 1,
+            "{} fetches of the tree for {batches} batches of resizes", 
+            conn.get_tree);
+    }
+
+    // Twelve windows in four columns, each balanced on a thread of its own
+    let mut id = 0;
+    let columns = (0..4)
+        .map(|i| container(&mut id, "con", 3, 0, false, (480 * i, 0, 480, 1080)))
+        .collect();
+    let workspace = node(&mut id, "workspace", "splith", (0, 0, 1920, 1080),
+        columns);
+    let tree: Node = serde_json::from_value(workspace).unwrap();
+
+    println!();
+    println!("{:>6} {:>8} {:>8}", "jobs", "ms", "speedup");
+    let serial = time_jobs(&tree, 1);
+    for jobs in [1, 2, 4] {
+        let elapsed = match jobs {
+            1 => serial,
+            _ => time_jobs(&tree, jobs),
+        };
+        println!("{jobs:>6} {:>8.1} {:>7.1}x", elapsed.as_secs_f64() * 1000.0,
+            serial.as_secs_f64() / elapsed.as_secs_f64());
+    }
+}
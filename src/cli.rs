@@ -0,0 +1,688 @@
+//! The command line interface
+
+use std::path::PathBuf;
+use std::time::Duration;
+use clap::{ArgMatches, Command, Arg};
+use clap::parser::ValueSource;
+use sway_balance_workspace::{Containers, Options, Ratio, Splits, Wm};
+use crate::config::Config;
+use crate::watch::Timing;
+
+
+/// What the exit status means, for the end of `--help`
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0  Balanced, or nothing needed balancing
+  1  Talking to the window manager failed, or some other error
+  2  Nothing needed balancing, with --exit-unchanged
+  3  Resizing failed, or some container did not converge in time (or some
+     window was not at its size after --verify, with --strict)
+  4  The check subcommand found the target imbalanced";
+
+
+/// Arguments shared by all subcommands
+fn global_args() -> Vec<Arg> {
+    vec![
+        Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .help("Log what is being balanced, repeat for more detail. \
+                RUST_LOG takes precedence")
+            .action(clap::ArgAction::Count),
+        Arg::new("quiet")
+            .long("quiet")
+            .short('q')
+            .help("Print nothing but errors, and the output asked for")
+            .conflicts_with("verbose")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("ipc")
+            .long("ipc")
+            .value_name("WM")
+            .help("The window manager to talk to")
+            .value_parser(["sway", "i3"])
+            .default_value("sway"),
+        Arg::new("socket")
+            .long("socket")
+            .short('s')
+            .value_name("PATH")
+            .help("The ipc socket to connect to. Takes precedence over \
+                I3SOCK and SWAYSOCK")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("exit-unchanged")
+            .long("exit-unchanged")
+            .help("Exit with 2 instead of 0 if nothing needed balancing")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("profile")
+            .long("profile")
+            .help("Print how long fetching trees and running commands took \
+                at the end, to stderr")
+            .action(clap::ArgAction::SetTrue),
+    ].into_iter().map(|arg| arg.global(true)).collect()
+}
+
+/// Arguments selecting what to balance
+fn target_args() -> Vec<Arg> {
+    vec![
+        Arg::new("focus")
+            .long("focus")
+            .short('f')
+            .help("Balance the focus, instead of the entire container")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("workspace")
+            .long("workspace")
+            .short('w')
+            .value_name("NAME")
+            .help("Balance the workspace with this name (or number), \
+                instead of the focused one"),
+        Arg::new("all")
+            .long("all")
+            .short('a')
+            .help("Balance every workspace")
+            .conflicts_with_all(["focus", "workspace"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("NAME")
+            .help("Balance every workspace on this output")
+            .conflicts_with_all(["focus", "workspace", "all"]),
+        Arg::new("root")
+            .long("root")
+            .help("Balance the entire tree, every workspace on every output \
+                in one go")
+            .conflicts_with_all(["focus", "workspace", "all", "output"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("mark")
+            .long("mark")
+            .value_name("MARK")
+            .help("Balance the container with this mark, wherever it is")
+            .conflicts_with_all(["focus", "workspace", "all", "output", "root",
+                "focus-child", "focus-neighbors", "level", "focus-output"]),
+        Arg::new("con-id")
+            .long("con-id")
+            .value_name("ID")
+            .help("Balance the container with this id, wherever it is, as \
+                listed by the list subcommand")
+            .value_parser(clap::value_parser!(i64))
+            .conflicts_with_all(["focus", "workspace", "all", "output", "root",
+                "mark", "focus-child", "focus-neighbors", "level", 
+                "focus-output"]),
+        Arg::new("focus-child")
+            .long("focus-child")
+            .help("Balance only the container holding the focused window")
+            .conflicts_with_all(["focus", "all", "output", "root"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("focus-neighbors")
+            .long("focus-neighbors")
+            .help("Balance only the focused window and its siblings, without \
+                going into the containers among them")
+            .conflicts_with_all(["focus", "all", "output", "root", 
+                "focus-child"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("level")
+            .long("level")
+            .value_name("N")
+            .help("Balance this many levels above the focus, 0 being what \
+                --focus balances, up to the whole workspace")
+            .value_parser(clap::value_parser!(usize))
+            .conflicts_with_all(["focus", "all", "output", "root", 
+                "focus-child", "focus-neighbors"]),
+        Arg::new("focus-output")
+            .long("focus-output")
+            .help("Balance the visible workspace on the output with the \
+                focus")
+            .conflicts_with_all(["focus", "workspace", "all", "output", "root",
+                "focus-child", "focus-neighbors", "level"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("include-hidden")
+            .long("include-hidden")
+            .help("With --focus-output, balance every workspace on it rather \
+                than only the visible one")
+            .requires("focus-output")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+/// Arguments deciding what balancing aims for, also used by the subcommands
+/// that only look at how far off that is
+fn weighing_args() -> Vec<Arg> {
+    vec![
+        Arg::new("layout")
+            .long("layout")
+            .short('l')
+            .help("Only balance horizontal (h) or vertical (v) splits")
+            .value_parser(["h", "v", "both"])
+            .default_value("both"),
+        Arg::new("only-type")
+            .long("only-type")
+            .value_name("TYPE")
+            .help("Only balance the children of workspaces, or of the \
+                containers within them (con)")
+            .value_parser(["con", "workspace", "all"])
+            .default_value("all"),
+        Arg::new("ratio")
+            .long("ratio")
+            .short('r')
+            .help("Split pairs of windows equally, or at the golden ratio \
+                in favour of the focus")
+            .value_parser(["equal", "golden"])
+            .default_value("equal"),
+        Arg::new("fib")
+            .long("fib")
+            .help("Split pairs of windows at Fibonacci ratios, closer to \
+                equal the deeper they are, for a spiral")
+            .conflicts_with_all(["ratio", "master", "equal-area"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("master")
+            .long("master")
+            .short('m')
+            .value_name("PCT")
+            .help("Give the focused window (or first) this percentage of \
+                the target, and split the rest between the others")
+            .value_parser(clap::value_parser!(u8).range(1..100))
+            .conflicts_with("ratio"),
+        Arg::new("equal-area")
+            .long("equal-area")
+            .help("Give every window the same area, rather than every child \
+                of a container the same size")
+            .conflicts_with_all(["ratio", "master"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("depth")
+            .long("depth")
+            .short('d')
+            .value_name("N")
+            .help("Only balance this many levels below the target, \
+                0 being its direct children")
+            .value_parser(clap::value_parser!(usize))
+            // That goes no deeper than the direct children anyway. It is
+            // declared here as the tree subcommand takes that, but not this.
+            .conflicts_with("focus-neighbors"),
+        Arg::new("no-descend")
+            .long("no-descend")
+            .help("Only balance the children of the target, not the \
+                containers among them, like --depth 0")
+            .conflicts_with("depth")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("exclude")
+            .long("exclude")
+            .short('x')
+            .value_name("APP_ID")
+            .help("Keep the size of windows with this app id, may be repeated")
+            .action(clap::ArgAction::Append),
+        Arg::new("exclude-mark")
+            .long("exclude-mark")
+            .value_name("MARK")
+            .help("Keep the size of windows (or containers) with this mark, \
+                may be repeated")
+            .action(clap::ArgAction::Append),
+        Arg::new("title-ratio-regex")
+            .long("title-ratio-regex")
+            .value_name("REGEX")
+            .help("Weigh windows by the number this regex captures from their \
+                title, like 'w=([0-9.]+)', those it does not match weigh 1")
+            .value_parser(|s: &str| regex::Regex::new(s)),
+        Arg::new("pin-focus")
+            .long("pin-focus")
+            .help("Keep the size of the focused window, and balance its \
+                siblings in the space left")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("min-width")
+            .long("min-width")
+            .value_name("PX")
+            .help("Do not make any window narrower than this")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .default_value("0"),
+        Arg::new("min-height")
+            .long("min-height")
+            .value_name("PX")
+            .help("Do not make any window lower than this")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .default_value("0"),
+        Arg::new("min-children")
+            .long("min-children")
+            .value_name("N")
+            .help("Only balance containers with at least this many children")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("2"),
+        Arg::new("grid")
+            .long("grid")
+            .value_name("PX")
+            .help("Round sizes down to a multiple of this, giving the rest \
+                to the last window")
+            .value_parser(clap::value_parser!(i32).range(1..))
+            .default_value("1"),
+    ]
+}
+
+/// Arguments deciding how to balance, and what else to do about it
+fn balance_args() -> Vec<Arg> {
+    vec![
+        Arg::new("yes")
+            .long("yes")
+            .short('y')
+            .help("Do not ask before balancing with --all or --root")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("dry-run")
+            .long("dry-run")
+            .short('n')
+            .help("Print the resize commands instead of executing them")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("aspect")
+            .long("aspect")
+            .value_name("W:H")
+            .help("Resize every window towards this aspect ratio, e.g. 16:9, \
+                instead of balancing")
+            .value_parser(parse_aspect)
+            .conflicts_with_all(["ratio", "fib", "master", "equal-area"]),
+        Arg::new("auto-orient")
+            .long("auto-orient")
+            .help("Split containers along their longer side, flipping the \
+                ones that are not before balancing them")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("tolerance")
+            .long("tolerance")
+            .short('t')
+            .value_name("PX")
+            .help("Leave windows alone that are at most this far off")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .default_value("2"),
+        Arg::new("min-px-change")
+            .long("min-px-change")
+            .value_name("PX")
+            .help("Do not send resizes smaller than this, though the other \
+                windows are still balanced")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .default_value("0"),
+        json_arg("Print the planned resizes as json, implies --dry-run"),
+        Arg::new("passes")
+            .long("passes")
+            .value_name("N")
+            .help("Balance everything again, up to this many times in all, \
+                for as long as the last time resized anything")
+            .value_parser(clap::value_parser!(u16).range(1..))
+            .default_value("1"),
+        Arg::new("show-diff")
+            .long("show-diff")
+            .help("Print a table of the sizes of windows now and after \
+                balancing, instead of resizing")
+            .conflicts_with_all(["json", "emit-script"])
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("emit-script")
+            .long("emit-script")
+            .help("Print the sway commands setting every window to its \
+                balanced size instead of resizing, e.g. for swaymsg")
+            .conflicts_with("json")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("timeout")
+            .long("timeout")
+            .value_name("MS")
+            .help("Give up on a container that has not converged after \
+                this many milliseconds")
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("gaps")
+            .long("gaps")
+            .value_name("PX")
+            .help("The inner gaps configured in sway, for --ppt and i3")
+            .value_parser(clap::value_parser!(i32).range(0..))
+            .default_value("0"),
+        Arg::new("pixels")
+            .long("pixels")
+            .value_name("UNIT")
+            .help("Whether the sizes in the other options are in sway's \
+                logical pixels, or the device pixels of scaled outputs")
+            .value_parser(["logical", "device"])
+            .default_value("logical"),
+        Arg::new("flatten")
+            .long("flatten")
+            .help("Remove containers around a single window (or container), \
+                as left behind by closing the others")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("grow-only")
+            .long("grow-only")
+            .help("Only grow windows that are too small, never shrink the \
+                ones that are too large")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("verify")
+            .long("verify")
+            .help("Check the sizes of windows after balancing, and correct \
+                them once if they are more than --tolerance off")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("strict")
+            .long("strict")
+            .help("Exit with 3 if some window is still off after --verify")
+            .requires("verify")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("grid-align")
+            .long("grid-align")
+            .help("Line up the rows across columns of equally many windows, \
+                so they form a grid")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("include-floating")
+            .long("include-floating")
+            .help("Also give all floating windows the same size, their \
+                average")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("bottom-up")
+            .long("bottom-up")
+            .help("Balance the innermost containers first")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("ppt")
+            .long("ppt")
+            .help("Set sizes in percentage points of the parent, instead of \
+                resizing by pixels")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("absolute")
+            .long("absolute")
+            .help("Set every window to its size in one go, leaving the \
+                window manager to make room, instead of growing and \
+                shrinking until it fits")
+            .conflicts_with("ppt")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("save-state")
+            .long("save-state")
+            .value_name("PATH")
+            .help("Save the sizes of the windows before balancing, so they \
+                can be restored later")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("jobs")
+            .long("jobs")
+            .short('j')
+            .value_name("N")
+            .help("Balance the subtrees below the target on this many \
+                threads at once, each with a connection of its own")
+            .value_parser(clap::value_parser!(u16).range(1..))
+            .default_value("1"),
+        Arg::new("max-commands")
+            .long("max-commands")
+            .value_name("N")
+            .help("Send at most this many resize commands, the largest \
+                first, and leave the rest for next time")
+            .value_parser(clap::value_parser!(usize))
+            .conflicts_with("jobs"),
+        Arg::new("pre-command")
+            .long("pre-command")
+            .value_name("CMD")
+            .help("Run this sway command before balancing, with {con_id} \
+                replaced by the id of the target. May be repeated.")
+            .action(clap::ArgAction::Append),
+        Arg::new("restore-focus")
+            .long("restore-focus")
+            .help("Focus the window that was focused before balancing again \
+                afterwards")
+            .action(clap::ArgAction::SetTrue),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Log a summary of what was balanced at the end (text), or \
+                print it to stdout as a single json object (json)")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .conflicts_with_all(["dry-run", "json", "show-diff", "emit-script",
+                "aspect"]),
+        Arg::new("notify")
+            .long("notify")
+            .help("Show a desktop notification summarizing what was \
+                balanced, if anything was")
+            .action(clap::ArgAction::SetTrue),
+    ]
+}
+
+/// Printing json instead of text, saying what gets printed in `help`
+fn json_arg(help: &'static str) -> Arg {
+    Arg::new("json")
+        .long("json")
+        .help(help)
+        .action(clap::ArgAction::SetTrue)
+}
+
+/// Balancing a tree read from a file rather than the window manager, for
+/// reproducing layouts offline
+fn simulate_arg() -> Arg {
+    Arg::new("simulate-from-json")
+        .long("simulate-from-json")
+        .value_name("FILE")
+        .help("Balance the tree in this file, as printed by \
+            `swaymsg -t get_tree`, and print the resulting sizes")
+        .value_parser(clap::value_parser!(PathBuf))
+        .hide(true)
+}
+
+/// Parse an aspect ratio like `16:9` into width over height
+fn parse_aspect(s: &str) -> Result<f64, String> {
+    let invalid = || format!("expected W:H, like 16:9, got {s}");
+    let (w, h) = s.split_once(':').ok_or_else(invalid)?;
+    let w: f64 = w.parse().map_err(|_| invalid())?;
+    let h: f64 = h.parse().map_err(|_| invalid())?;
+
+    match w > 0.0 && h > 0.0 {
+        true => Ok(w / h),
+        false => Err(invalid()),
+    }
+}
+
+
+pub fn command() -> Command {
+    Command::new("sway-balance")
+        .author("Rintse")
+        .about("Balance a sway workspace, or some focus therein")
+        .after_help(EXIT_CODES)
+        .args(global_args())
+        // Without a subcommand we balance, like we always have
+        .args(target_args())
+        .args(weighing_args())
+        .args(balance_args())
+        .arg(simulate_arg())
+        .subcommand(Command::new("balance")
+            .about("Balance once (the default)")
+            .args(target_args())
+            .args(weighing_args())
+            .args(balance_args())
+            .arg(simulate_arg()))
+        .subcommand(Command::new("watch")
+            .visible_alias("daemon")
+            .about("Keep running, and balance again whenever windows are \
+                opened, closed or moved")
+            .args(target_args())
+            .args(weighing_args())
+            .args(balance_args())
+            .arg(Arg::new("debounce")
+                .long("debounce")
+                .value_name("MS")
+                .help("Wait until no events have come in for this long \
+                    before balancing")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("150"))
+            .arg(Arg::new("interval")
+                .long("interval")
+                .value_name("MS")
+                .help("Balance at most once per this many milliseconds, \
+                    however many events come in")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0"))
+            .arg(Arg::new("events-json")
+                .long("events-json")
+                .help("Print a json line to stdout for every balance, with \
+                    what it did and the event that caused it")
+                .conflicts_with("format")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(Command::new("check")
+            .about("Report how imbalanced the target is, without resizing \
+                anything. With --json for every container, otherwise only \
+                the worst.")
+            .args(target_args())
+            .args(weighing_args())
+            .arg(json_arg("Print how imbalanced every container is as json"))
+            .arg(Arg::new("threshold")
+                .long("threshold")
+                .value_name("PCT")
+                .help("Exit with 4 if any window is further off its size \
+                    than this percentage")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("5")))
+        .subcommand(Command::new("list")
+            .about("List every container in the target that balancing would \
+                resize, with how imbalanced it is, e.g. to pick one for \
+                --con-id. As a table, or json with --json.")
+            .args(target_args())
+            .args(weighing_args())
+            .arg(json_arg("Print the containers as json instead of a table")))
+        .subcommand(Command::new("tree")
+            .about("Print the tree below what would be balanced, for \
+                debugging")
+            .args(target_args()))
+        .subcommand(Command::new("restore")
+            .about("Restore the window sizes saved with --save-state")
+            .arg(Arg::new("path")
+                .value_name("PATH")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))))
+}
+
+/// The value of the argument `id`, unless it was left at its default and
+/// `config` has one
+fn or_config<T>(m: &ArgMatches, id: &str, config: Option<T>) -> T
+where T: Clone + Send + Sync + 'static
+{
+    match (m.value_source(id), config) {
+        (Some(ValueSource::DefaultValue), Some(value)) => value,
+        _ => m.get_one::<T>(id).unwrap().clone(),
+    }
+}
+
+/// The balancing knobs set in `m`, the matches of the weighing and balance
+/// arguments, and in `config` where `m` does not set them
+pub fn options(m: &ArgMatches, config: &Config) -> Options {
+    Options {
+        dry_run: m.get_flag("dry-run"),
+        passes: *m.get_one::<u16>("passes").unwrap() as usize,
+        tolerance: or_config(m, "tolerance", config.tolerance),
+        min_px_change: *m.get_one::<i32>("min-px-change").unwrap(),
+        timeout: m.get_one::<u64>("timeout")
+            .map(|ms| Duration::from_millis(*ms)),
+        ppt: m.get_flag("ppt"),
+        absolute: m.get_flag("absolute"),
+        max_commands: m.get_one::<usize>("max-commands").copied(),
+        auto_orient: m.get_flag("auto-orient"),
+        bottom_up: m.get_flag("bottom-up"),
+        flatten: m.get_flag("flatten"),
+        grow_only: m.get_flag("grow-only"),
+        grid_align: m.get_flag("grid-align"),
+        verify: m.get_flag("verify"),
+        gaps: *m.get_one::<i32>("gaps").unwrap(),
+        // Asking the outputs for their scale needs a connection
+        device_scales: Default::default(),
+        ..weighing(m, config)
+    }
+}
+
+/// The knobs deciding what balancing aims for set in `m`, the matches of 
+/// the weighing arguments, and in `config` where `m` does not set them. The
+/// others are left at their defaults.
+pub fn weighing(m: &ArgMatches, config: &Config) -> Options {
+    let layout = or_config(m, "layout", config.layout.clone());
+    let ratio = or_config(m, "ratio", config.ratio.clone());
+
+    Options {
+        wm: match m.get_one::<String>("ipc").map(String::as_str) {
+            Some("i3") => Wm::I3,
+            _ => Wm::Sway,
+        },
+        splits: match layout.as_str() {
+            "h" => Splits::Horizontal,
+            "v" => Splits::Vertical,
+            _ => Splits::Both,
+        },
+        containers: match m.get_one::<String>("only-type").unwrap().as_str() {
+            "con" => Containers::Con,
+            "workspace" => Containers::Workspace,
+            _ => Containers::All,
+        },
+        ratio: match ratio.as_str() {
+            _ if m.get_flag("fib") => Ratio::Fib,
+            "golden" => Ratio::Golden,
+            _ => Ratio::Equal,
+        },
+        // A single container, for a keybinding that should be quick
+        depth: match m.get_flag("focus-neighbors") 
+            || m.get_flag("no-descend") 
+        {
+            true => Some(0),
+            false => m.get_one::<usize>("depth").copied(),
+        },
+        exclude_app_ids: m.get_many::<String>("exclude")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        exclude_marks: m.get_many::<String>("exclude-mark")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        pin_focus: m.get_flag("pin-focus"),
+        min_width: or_config(m, "min-width", config.min_width),
+        min_height: or_config(m, "min-height", config.min_height),
+        master: m.get_one::<u8>("master").copied(),
+        app_weights: config.weights.clone(),
+        title_weight: title_weight(m),
+        equal_area: m.get_flag("equal-area"),
+        min_children: *m.get_one::<usize>("min-children").unwrap(),
+        grid: *m.get_one::<i32>("grid").unwrap(),
+        ..Default::default()
+    }
+}
+
+/// The regex to take weights from titles with, if there is one with a group
+/// to capture them in
+fn title_weight(m: &ArgMatches) -> Option<regex::Regex> {
+    let re = m.get_one::<regex::Regex>("title-ratio-regex")?;
+    if re.captures_len() < 2 {
+        tracing::warn!("--title-ratio-regex {re} captures nothing, ignoring it");
+        return None
+    }
+    Some(re.clone())
+}
+
+/// How the watch subcommand in `m` paces its balancing
+pub fn timing(m: &ArgMatches) -> Timing {
+    let ms = |id| Duration::from_millis(*m.get_one::<u64>(id).unwrap());
+    Timing { debounce: ms("debounce"), interval: ms("interval") }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_agree_with_each_other() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn focus_neighbors_takes_no_depth() {
+        let res = command().try_get_matches_from(["sway-balance", 
+            "--focus-neighbors", "--depth", "2"]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn looking_takes_no_balance_arguments() {
+        for sub in ["check", "list"] {
+            let res = command()
+                .try_get_matches_from(["sway-balance", sub, "--dry-run"]);
+            assert!(res.is_err(), "{sub} takes --dry-run");
+
+            let m = command()
+                .get_matches_from(["sway-balance", sub, "--min-width", "300"]);
+            let (_, m) = m.subcommand().unwrap();
+            assert_eq!(weighing(m, &Config::default()).min_width, 300);
+        }
+    }
+
+    #[test]
+    fn no_descend_only_balances_the_children() {
+        let m = command()
+            .get_matches_from(["sway-balance", "balance", "--no-descend"]);
+        let (_, m) = m.subcommand().unwrap();
+        assert_eq!(options(m, &Config::default()).depth, Some(0));
+    }
+}
@@ -0,0 +1,70 @@
+//! Persistent settings, from `$XDG_CONFIG_HOME/sway-balance/config.toml`
+//!
+//! ```toml
+//! tolerance = 4
+//! layout = "h"
+//!
+//! [weights]
+//! firefox = 2
+//! Alacritty = 1
+//! ```
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use serde::Deserialize;
+use sway_balance_workspace::AppError;
+
+
+/// The settings in the config file. Everything is optional, and anything
+/// left out falls back to the defaults of the command line. Flags given on
+/// the command line override these.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The weight of windows by app id, unless they are marked with one
+    pub weights: HashMap<String, f64>,
+    pub tolerance: Option<i32>,
+    pub layout: Option<String>,
+    pub ratio: Option<String>,
+    pub min_width: Option<i32>,
+    pub min_height: Option<i32>,
+}
+
+
+/// Where the config file is, if there is any place to look for one
+fn path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("sway-balance").join("config.toml"))
+}
+
+/// Load the config file, or the empty default config if there is none
+pub fn load() -> Result<Config, AppError> {
+    let Some(path) = path() else { return Ok(Config::default()) };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(AppError::Config(path, e.to_string())),
+    };
+    let config: Config = toml::from_str(&text)
+        .map_err(|e| AppError::Config(path.clone(), e.message().to_string()))?;
+
+    // Check these here, rather than silently falling back on a typo later
+    let invalid = |what: &str| AppError::Config(path.clone(), what.to_string());
+    if config.layout.as_deref().is_some_and(|l| !["h", "v", "both"].contains(&l)) {
+        return Err(invalid("layout must be one of h, v or both"));
+    }
+    if config.ratio.as_deref().is_some_and(|r| !["equal", "golden"].contains(&r)) {
+        return Err(invalid("ratio must be one of equal or golden"));
+    }
+    if config.weights.values().any(|w| *w <= 0.0) {
+        return Err(invalid("weights must be positive"));
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,1858 @@
+//! Balance a sway workspace, or some subset thereof.
+//!
+//! ```no_run
+//! use sway_balance_workspace::{balance, connect, find_by_id, AppError};
+//! use sway_balance_workspace::provider::LiveProvider;
+//!
+//! fn main() -> Result<(), AppError> {
+//!     let mut conn = LiveProvider::from(connect(None)?);
+//!     let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
+//!     let workspaces = conn.get_workspaces()
+//!         .map_err(|_| AppError::GetWorkspaces)?;
+//!
+//!     let focused = workspaces.iter().find(|w| w.focused)
+//!         .ok_or(AppError::NoFocus)?;
+//!     let node = find_by_id(&tree, focused.id).ok_or(AppError::NoFocus)?;
+//!
+//!     let report = balance(&mut conn, node)?;
+//!     println!("{report}");
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use swayipc::{Connection, Node, NodeLayout, NodeType, Rect, Workspace};
+use swayipc::Error::CommandParse;
+
+pub mod output;
+pub mod provider;
+pub mod state;
+#[cfg(test)]
+mod tests;
+
+use provider::{CachingProvider, TreeProvider};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("Could not open a connection to sway") ]
+    Conn(#[source] swayipc::Error),
+    #[error("Neither SWAYSOCK nor I3SOCK is set, and no window manager knows \
+        its socket. Run this from within the session or pass --socket") ]
+    NoSocketEnv,
+    #[error("No window manager listening on {}, is it running?", .0.display()) ]
+    SocketMissing(PathBuf, #[source] std::io::Error),
+    #[error("Not allowed to connect to {}, is it another user's?", .0.display()) ]
+    SocketPermission(PathBuf, #[source] std::io::Error),
+    #[error("{} does not speak the i3/sway ipc protocol", .0.display()) ]
+    ProtocolMismatch(PathBuf, #[source] swayipc::Error),
+    #[error("Could not get the node layout tree") ]
+    GetTree,
+    #[error("Could not get the workspaces") ]
+    GetWorkspaces,
+    #[error("Could not get the outputs") ]
+    GetOutputs,
+    #[error("Error issuing reize command") ]
+    Resize,
+    #[error("Node disappeared while running") ]
+    NodeGone,
+    #[error("Current focus could not be determined") ]
+    NoFocus,
+    #[error("The workspace was not in the tree, it kept changing while \
+        fetching both") ]
+    TreeMismatch,
+    #[error("No workspace named \"{0}\"") ]
+    WorkspaceNotFound(String),
+    #[error("No output named \"{0}\"") ]
+    OutputNotFound(String),
+    #[error("No container marked \"{0}\"") ]
+    MarkNotFound(String),
+    #[error("This would balance {0} workspace(s) with {1} container(s), \
+        run again with --yes (or --dry-run to see how)") ]
+    Unconfirmed(usize, usize),
+    #[error("Balancing failed for {0} workspace(s)") ]
+    WorkspacesFailed(usize),
+    #[error("Could not subscribe to sway events") ]
+    Subscribe,
+    #[error("Could not install the signal handlers") ]
+    Signals,
+    #[error("Could not save the state to {}", .0.display()) ]
+    SaveState(PathBuf),
+    #[error("Could not load the state from {}", .0.display()) ]
+    LoadState(PathBuf),
+    #[error("Pre-command \"{0}\" failed: {1}") ]
+    PreCommand(String, String),
+    #[error("Could not restore the focus") ]
+    Focus,
+    #[error("Invalid config {}: {1}", .0.display()) ]
+    Config(PathBuf, String),
+    #[error("Could not read a tree from {}", .0.display()) ]
+    Simulate(PathBuf),
+    #[error("Balancing failed for container(s) {}", con_ids(.0)) ]
+    Partial(Vec<(i64, AppError)>),
+}
+
+/// The ids of the containers in `failed`, comma separated
+fn con_ids(failed: &[(i64, AppError)]) -> String {
+    failed.iter()
+        .map(|(id, _)| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+
+/// Knobs for `balance_with`
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Print the resize commands to stdout instead of sending them to sway
+    pub dry_run: bool,
+    /// Which window manager is on the other end of the socket
+    pub wm: Wm,
+    /// Which split containers get their children resized
+    pub splits: Splits,
+    /// Balance the whole tree again up to this many times, as long as the
+    /// previous time resized anything. 0 is the same as 1.
+    pub passes: usize,
+    /// Which types of containers get their children resized
+    pub containers: Containers,
+    /// How to divide a container between its children
+    pub ratio: Ratio,
+    /// Children at most this many pixels off are considered balanced
+    pub tolerance: i32,
+    /// Resizes smaller than this many pixels are not sent. Unlike 
+    /// `tolerance` this does not make a child count as balanced, the others
+    /// are still resized as usual.
+    pub min_px_change: i32,
+    /// How many levels below the root to balance, `Some(0)` only balances
+    /// the root's direct children
+    pub depth: Option<usize>,
+    /// How long to keep retrying a container that will not converge
+    pub timeout: Option<Duration>,
+    /// Set sizes as a percentage of the parent instead of growing and
+    /// shrinking by pixels. This avoids rounding errors adding up in nested
+    /// containers, at the cost of only being precise up to 1% of the parent.
+    pub ppt: bool,
+    /// Set every child to its size in pixels in one go, and leave finding
+    /// the space for it to the window manager, instead of growing and 
+    /// shrinking children until they fit. Balancing then takes a single pass,
+    /// and one more to check on it.
+    pub absolute: bool,
+    /// Windows with these app ids keep their size, their siblings share the
+    /// rest of the space
+    pub exclude_app_ids: HashSet<String>,
+    /// Like `exclude_app_ids`, for nodes with any of these marks
+    pub exclude_marks: HashSet<String>,
+    /// Stop after sending this many resize commands in total, leaving out
+    /// the smallest resizes of a container first
+    pub max_commands: Option<usize>,
+    /// Like `exclude_app_ids`, for the focused window. The containers
+    /// holding it are balanced as usual.
+    pub pin_focus: bool,
+    /// No window is made narrower than this
+    pub min_width: i32,
+    /// No window is made lower than this
+    pub min_height: i32,
+    /// Give the focused (or first) child of the root this percentage of the
+    /// space, and let the others share the rest
+    pub master: Option<u8>,
+    /// The weight of windows by app id, for those not marked with one
+    pub app_weights: HashMap<String, f64>,
+    /// Extracts the weight of windows from their title, with its first 
+    /// capture group. Marks take precedence, app ids do not.
+    pub title_weight: Option<regex::Regex>,
+    /// Flip split containers to split along their longer side before
+    /// balancing them
+    pub auto_orient: bool,
+    /// Give every visible window the same area, rather than every child of
+    /// a container the same size
+    pub equal_area: bool,
+    /// Leave containers with fewer children than this alone, though not the
+    /// containers below them
+    pub min_children: usize,
+    /// Balance the innermost containers first, instead of the outermost
+    pub bottom_up: bool,
+    /// Remove containers holding a single child, putting the child in their
+    /// place. Needs sway 1.8 or later.
+    pub flatten: bool,
+    /// Only grow children, and leave the ones that are too large to shrink
+    /// as their neighbours grow
+    pub grow_only: bool,
+    /// Line up the rows of columns (or the columns of rows) that form a 
+    /// grid, instead of balancing every column on its own
+    pub grid_align: bool,
+    /// Check the sizes after resizing, rather than trusting the commands did
+    /// what they said, and correct them once if they are off
+    pub verify: bool,
+    /// The inner gaps between windows. Only matters when sizes are set as a
+    /// percentage: without it the percentages come out too large, and
+    /// gapped layouts drift on repeated runs.
+    pub gaps: i32,
+    /// Round sizes down to a multiple of this along the axis of their
+    /// container, e.g. the cell size of a terminal. The last child of a
+    /// container takes up the difference.
+    pub grid: i32,
+    /// The scale of every output by name, if the sizes in these options are
+    /// in device pixels. Empty if they are in logical pixels, the ones sway
+    /// lays out and resizes in.
+    pub device_scales: HashMap<String, f64>,
+}
+
+impl Options {
+    /// These options with the sizes in them converted from device pixels to
+    /// logical ones, on an output scaled by `scale`
+    fn in_logical_pixels(&self, scale: f64) -> Options {
+        let px = |v: i32| (v as f64 / scale).round() as i32;
+        Options {
+            tolerance: px(self.tolerance),
+            min_px_change: px(self.min_px_change),
+            min_width: px(self.min_width),
+            min_height: px(self.min_height),
+            grid: px(self.grid).max(1),
+            ..self.clone()
+        }
+    }
+}
+
+/// How the space in a container is divided between its children
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ratio {
+    /// By weight, which is equal unless set with a mark
+    #[default]
+    Equal,
+    /// Pairs of children are split at the golden ratio, the larger part going
+    /// to the one holding the focus. Other containers are split equally.
+    Golden,
+    /// Pairs of children are split at the ratio of successive Fibonacci
+    /// numbers, starting at 13/21 at the root and going down to 1/2 deeper
+    /// in the tree. The larger part goes to a window rather than a container,
+    /// so nested pairs spiral inwards. Other containers are split equally.
+    Fib,
+}
+
+/// Which orientations of split containers to balance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Splits {
+    #[default]
+    Both,
+    Horizontal,
+    Vertical,
+}
+
+impl Splits {
+    /// Whether a container with `layout` should be balanced
+    fn allows(self, layout: NodeLayout) -> bool {
+        match self {
+            Splits::Both => true,
+            Splits::Horizontal => layout == NodeLayout::SplitH,
+            Splits::Vertical => layout == NodeLayout::SplitV,
+        }
+    }
+}
+
+/// Which types of containers to balance the children of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Containers {
+    #[default]
+    All,
+    /// Only the splits made within a workspace
+    Con,
+    /// Only workspaces themselves
+    Workspace,
+}
+
+impl Containers {
+    /// Whether a container of `node_type` should be balanced
+    fn allows(self, node_type: NodeType) -> bool {
+        match self {
+            Containers::All => true,
+            Containers::Con => node_type == NodeType::Con,
+            Containers::Workspace => node_type == NodeType::Workspace,
+        }
+    }
+}
+
+/// The window managers speaking the ipc protocol. They mostly agree, except
+/// for the phrasing of some commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Wm {
+    #[default]
+    Sway,
+    I3,
+}
+
+
+/// Connect to the window manager over the socket at `path`, or the one
+/// advertised in the environment (`I3SOCK`/`SWAYSOCK`) if there is none, or
+/// else the one the window manager itself says it listens on. An explicit 
+/// path takes precedence over the environment.
+pub fn connect(path: Option<&Path>) -> Result<Connection, AppError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => std::env::var_os("I3SOCK")
+            .or_else(|| std::env::var_os("SWAYSOCK"))
+            .map(PathBuf::from)
+            // Not always set when started from a keybinding or a service,
+            // so ask the window managers like swayipc does
+            .or_else(|| ask_socket_path("i3"))
+            .or_else(|| ask_socket_path("sway"))
+            .ok_or(AppError::NoSocketEnv)?,
+    };
+
+    let stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+        // A socket left behind by a window manager that is gone refuses
+        ErrorKind::NotFound | ErrorKind::ConnectionRefused => {
+            AppError::SocketMissing(path.clone(), e)
+        },
+        ErrorKind::PermissionDenied => AppError::SocketPermission(path.clone(), e),
+        _ => AppError::Conn(e.into()),
+    })?;
+
+    // Anything can be listening on a socket, make sure it is a window manager
+    let mut conn = Connection::from(stream);
+    conn.get_version().map_err(|e| match e {
+        swayipc::Error::Io(_) => AppError::Conn(e),
+        _ => AppError::ProtocolMismatch(path, e),
+    })?;
+    Ok(conn)
+}
+
+/// The socket `wm --get-socketpath` prints, if it is installed and knows of
+/// one
+fn ask_socket_path(wm: &str) -> Option<PathBuf> {
+    let out = std::process::Command::new(wm)
+        .arg("--get-socketpath")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let path = String::from_utf8(out.stdout).ok()?;
+    let path = path.trim_end();
+
+    match out.status.success() && !path.is_empty() {
+        true => Some(PathBuf::from(path)),
+        false => None,
+    }
+}
+
+
+/// Breadth first search for the first node for which `predicate` holds
+pub fn bfs_find<'a>(root: &'a Node, predicate: impl Fn(&'a Node) -> bool)
+-> Option<&'a Node> 
+{
+    let mut q = VecDeque::from(vec![root]);
+
+    while let Some(n) = q.pop_front() {
+        if predicate(n) { 
+            return Some(n) 
+        }
+
+        q.extend(n.nodes.iter());
+    };
+
+    None // Never found
+}
+
+/// Call `visit` with every node in the tree, and its depth below `root`, in
+/// breadth first order
+pub fn bfs_visit<'a>(root: &'a Node, mut visit: impl FnMut(&'a Node, usize)) {
+    let mut q = VecDeque::from(vec![(root, 0)]);
+
+    while let Some((n, depth)) = q.pop_front() {
+        visit(n, depth);
+        q.extend(n.nodes.iter().map(|c| (c, depth + 1)));
+    }
+}
+
+/// All nodes for which `predicate` holds, in breadth first order
+pub fn bfs_collect<'a>(root: &'a Node, predicate: impl Fn(&'a Node) -> bool)
+-> Vec<&'a Node> 
+{
+    let mut found = Vec::new();
+    bfs_visit(root, |n, _| if predicate(n) { found.push(n) });
+    found
+}
+
+/// Find a node with `id` in some (sub-)tree
+pub fn find_by_id(root: &Node, id: i64) -> Option<&Node> {
+    bfs_find(root, |n| n.id == id)
+}
+
+/// Find the highest level node that is focused. 
+/// This should be the "largest" container that is focused
+/// Floating windows are never balanced, and not searched for here either, so
+/// if the focus is floating this is the (tiled) node holding it in its
+/// `floating_nodes` instead.
+pub fn top_focus(root: &Node) -> Option<&Node> {
+    bfs_find(root, |n| n.focused)
+        .or_else(|| bfs_find(root, |n| n.floating_nodes.iter()
+            .any(|f| f.find_as_ref(|n| n.focused).is_some())))
+}
+
+/// Find the node that has the node with `id` as a direct child
+pub fn parent_of(root: &Node, id: i64) -> Option<&Node> {
+    bfs_find(root, |n| n.nodes.iter().any(|c| c.id == id))
+}
+
+/// Find the node marked with `mark`
+pub fn find_marked<'a>(root: &'a Node, mark: &str)
+-> Result<&'a Node, AppError> {
+    bfs_find(root, |n| n.marks.iter().any(|m| m == mark))
+        .ok_or_else(|| AppError::MarkNotFound(mark.to_string()))
+}
+
+/// The nodes from `root` down to the node with `id`, both included. Empty if
+/// there is no such node below `root`.
+pub fn path_to(root: &Node, id: i64) -> Vec<&Node> {
+    if root.id == id { return vec![root] }
+
+    for child in root.nodes.iter().chain(&root.floating_nodes) {
+        let mut path = path_to(child, id);
+        if !path.is_empty() {
+            path.insert(0, root);
+            return path;
+        }
+    }
+    vec![]
+}
+
+/// Find a workspace by its name, or by its number if `name` is one
+pub fn find_workspace<'a>(workspaces: &'a [Workspace], name: &str)
+-> Result<&'a Workspace, AppError> {
+    let by_name = workspaces.iter().find(|w| w.name == name);
+    let by_num = || name.parse::<i32>().ok()
+        .and_then(|num| workspaces.iter().find(|w| w.num == num));
+
+    by_name.or_else(by_num)
+        .ok_or_else(|| AppError::WorkspaceNotFound(name.to_string()))
+}
+
+/// Find the output node called `name`
+pub fn find_output<'a>(root: &'a Node, name: &str) 
+-> Result<&'a Node, AppError> {
+    bfs_find(root, |n| n.node_type == NodeType::Output 
+            && n.name.as_deref() == Some(name))
+        .ok_or_else(|| AppError::OutputNotFound(name.to_string()))
+}
+
+/// All workspace nodes in the tree, excluding the scratchpad
+/// Workspaces sit right below the outputs in sway, but in i3 they are wrapped
+/// in an extra "content" container, so search for them rather than assume.
+pub fn workspace_nodes(root: &Node) -> Vec<&Node> {
+    bfs_collect(root, |n| n.node_type == NodeType::Workspace
+        && n.name.as_deref() != Some("__i3_scratch"))
+}
+
+/// The workspace holding the focus, found through the tree rather than the
+/// workspace list. Not every setup marks a workspace as focused in the latter.
+pub fn focused_workspace_node(root: &Node) -> Option<&Node> {
+    workspace_nodes(root).into_iter().find(|w| top_focus(w).is_some())
+}
+
+/// The workspace node `workspaces` says is focused, or the one holding the 
+/// focus in the tree if none is. On some multi-seat or just started setups no
+/// workspace in the list is.
+pub fn focused_workspace<'a>(root: &'a Node, workspaces: &[Workspace])
+-> Option<&'a Node> {
+    match workspaces.iter().find(|w| w.focused) {
+        Some(workspace) => find_by_id(root, workspace.id),
+        None => focused_workspace_node(root),
+    }
+}
+
+
+/// The axis along which a split container divides its space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    /// The axis the children of a container with `layout` share, if it is a
+    /// split
+    fn of(layout: NodeLayout) -> Option<Axis> {
+        match layout {
+            NodeLayout::SplitH => Some(Axis::Horizontal),
+            NodeLayout::SplitV => Some(Axis::Vertical),
+            _ => None,
+        }
+    }
+
+    /// The size of `node` along this axis
+    fn dim(self, node: &Node) -> i32 {
+        match self {
+            Axis::Horizontal => node.rect.width,
+            Axis::Vertical => node.rect.height,
+        }
+    }
+
+    /// What `resize set` calls the size along this axis
+    fn name(self) -> &'static str {
+        match self {
+            Axis::Horizontal => "width",
+            Axis::Vertical => "height",
+        }
+    }
+
+    /// The direction in which resizing a child moves its edge with the next
+    /// sibling
+    fn grow_dir(self) -> &'static str {
+        match self {
+            Axis::Horizontal => "right",
+            Axis::Vertical => "down",
+        }
+    }
+
+    /// The direction in which resizing a child moves its edge with the
+    /// previous sibling instead
+    fn shrink_dir(self) -> &'static str {
+        match self {
+            Axis::Horizontal => "left",
+            Axis::Vertical => "up",
+        }
+    }
+
+    /// No window is made smaller than this along this axis
+    fn min(self, opts: &Options) -> i32 {
+        match self {
+            Axis::Horizontal => opts.min_width,
+            Axis::Vertical => opts.min_height,
+        }
+    }
+}
+
+
+/// The weight of a node, as set by a `balance-weight=<w>` mark, or otherwise
+/// in its title by `Options::title_weight`, or for its app id in 
+/// `Options::app_weights`. Nodes without any (or with a nonsensical one) 
+/// weigh 1.
+fn weight(node: &Node, opts: &Options) -> f64 {
+    let marked = node.marks.iter()
+        .filter_map(|m| m.strip_prefix("balance-weight="))
+        .find_map(|w| w.parse::<f64>().ok());
+    let by_title = || opts.title_weight.as_ref()
+        .zip(node.name.as_deref())
+        .and_then(|(re, title)| re.captures(title)?.get(1))
+        .and_then(|w| w.as_str().parse::<f64>().ok());
+    let by_app = || node.app_id.as_ref()
+        .and_then(|id| opts.app_weights.get(id))
+        .copied();
+
+    marked.or_else(by_title).or_else(by_app)
+        .filter(|w| *w > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// Whether `node` is marked `balance-skip`, leaving it and everything below
+/// it as it is
+fn is_skipped(node: &Node) -> bool {
+    node.marks.iter().any(|m| m == "balance-skip")
+}
+
+/// The ids of every node in a subtree below `root` marked `balance-skip`,
+/// including those of the marked nodes themselves
+fn skipped_ids(root: &Node) -> HashSet<i64> {
+    let mut ids = HashSet::new();
+    for skipped in bfs_collect(root, is_skipped) {
+        bfs_visit(skipped, |n, _| { ids.insert(n.id); });
+    }
+    ids
+}
+
+/// The layout to balance `node` as: its own, or if nothing is resized for
+/// real (`marked`) the one set by a `balance-layout=splith` or
+/// `balance-layout=splitv` mark. Sway resizes a split along its own axis
+/// whatever it is balanced as, so the mark only helps to look at the targets.
+fn layout_of(node: &Node, marked: bool) -> NodeLayout {
+    if !marked { return node.layout }
+    node.marks.iter()
+        .filter_map(|m| m.strip_prefix("balance-layout="))
+        .find_map(|l| match l {
+            "splith" => Some(NodeLayout::SplitH),
+            "splitv" => Some(NodeLayout::SplitV),
+            _ => None,
+        })
+        .unwrap_or(node.layout)
+}
+
+/// The split layout fitting the shape of `node` better than its current one,
+/// if it is a split of more than one child that is wider than high, or the
+/// other way around
+fn fitting_layout(node: &Node) -> Option<&'static str> {
+    if node.nodes.len() < 2 { return None }
+
+    let Rect { width, height, .. } = node.rect;
+    match node.layout {
+        NodeLayout::SplitV if width > height => Some("splith"),
+        NodeLayout::SplitH if height > width => Some("splitv"),
+        _ => None,
+    }
+}
+
+/// Whether `node` should keep its size while its siblings are balanced
+fn is_fixed(node: &Node, opts: &Options) -> bool {
+    node.app_id.as_ref().is_some_and(|id| opts.exclude_app_ids.contains(id))
+        || node.marks.iter().any(|m| opts.exclude_marks.contains(m))
+        || opts.pin_focus && node.focused
+}
+
+/// The size each of `children` should have to share `sum_dim`. Fixed children
+/// keep their current size, the rest share whatever space that leaves, each
+/// getting at least `min`. If all children are fixed there is nothing to 
+/// balance.
+fn targets(children: &[Node], axis: Axis, sum_dim: i32, min: i32, 
+    depth: usize, opts: &Options) -> Option<Vec<i32>> 
+{
+    let (fixed, free): (Vec<&Node>, Vec<&Node>) = children.iter()
+        .partition(|c| is_fixed(c, opts));
+    if free.is_empty() { return None }
+
+    let available = sum_dim - fixed.into_iter().map(|c| axis.dim(c))
+        .sum::<i32>();
+    let weights = match opts.master {
+        Some(pct) if depth == 0 => master_weights(&free, pct),
+        _ => weights(&free, depth, opts),
+    };
+    let mut shares = apportion_min(available, &weights, min)
+        .unwrap_or_else(|| {
+            tracing::warn!("not enough space to give every child {min}px");
+            apportion(available, &weights)
+        });
+    if opts.grid > 1 { snap(&mut shares, opts.grid) }
+    let mut shares = shares.into_iter();
+
+    Some(children.iter()
+        .map(|c| match is_fixed(c, opts) {
+            true => axis.dim(c),
+            false => shares.next().unwrap(),
+        })
+        .collect())
+}
+
+/// The split of pairs by depth for `Ratio::Fib`: the ratios of successive
+/// Fibonacci numbers, ending in an equal split
+const FIB: [(f64, f64); 6] = [
+    (13.0, 8.0),
+    (8.0, 5.0),
+    (5.0, 3.0),
+    (3.0, 2.0),
+    (2.0, 1.0),
+    (1.0, 1.0),
+];
+
+/// The relative share of space `children` should get, according to the ratio
+fn weights(children: &[&Node], depth: usize, opts: &Options) -> Vec<f64> {
+    if let (Ratio::Golden, [first, second]) = (opts.ratio, children) {
+        let second_focused = top_focus(second).is_some() 
+            && top_focus(first).is_none();
+        return match second_focused {
+            true => vec![0.382, 0.618],
+            false => vec![0.618, 0.382],
+        }
+    }
+    if let (Ratio::Fib, [first, second]) = (opts.ratio, children) {
+        let (larger, smaller) = FIB[depth.min(FIB.len() - 1)];
+        let first_deeper = !first.nodes.is_empty() && second.nodes.is_empty();
+        return match first_deeper {
+            true => vec![smaller, larger],
+            false => vec![larger, smaller],
+        }
+    }
+
+    children.iter()
+        .map(|c| match opts.equal_area {
+            true => weight(c, opts) * tiles(c) as f64,
+            false => weight(c, opts),
+        })
+        .collect()
+}
+
+/// How many windows are visible in `node`. Sizing every child by this gives
+/// each window the same area, all the way down: a child with k of the n tiles
+/// gets k/n of the area, which its own children divide by the same rule. This
+/// needs no more than the usual pass over the tree.
+fn tiles(node: &Node) -> usize {
+    match node.layout {
+        // Only one child of these is ever visible
+        NodeLayout::Tabbed | NodeLayout::Stacked => 1,
+        _ if node.nodes.is_empty() => 1,
+        _ => node.nodes.iter().map(tiles).sum(),
+    }
+}
+
+/// The relative share of space `children` should get if the focused one (or
+/// the first, if the focus is elsewhere) is to get `pct` percent of it
+fn master_weights(children: &[&Node], pct: u8) -> Vec<f64> {
+    let master = children.iter()
+        .position(|c| top_focus(c).is_some())
+        .unwrap_or(0);
+    let others = (children.len() - 1).max(1) as f64;
+
+    (0..children.len())
+        .map(|i| match i == master {
+            true => pct as f64,
+            false => (100 - pct) as f64 / others,
+        })
+        .collect()
+}
+
+/// Round all `shares` but the last down to a multiple of `grid`, the last one
+/// gets what that leaves over
+fn snap(shares: &mut [i32], grid: i32) {
+    let Some((last, rest)) = shares.split_last_mut() else { return };
+
+    for share in rest.iter_mut() {
+        let snapped = *share / grid * grid;
+        *last += *share - snapped;
+        *share = snapped;
+    }
+}
+
+/// Divide `sum_dim` pixels by `weights`. The shares are rounded down, and the
+/// pixels that leaves over are handed out one at a time from the first share
+/// on, so that the shares add up to exactly `sum_dim`.
+fn apportion(sum_dim: i32, weights: &[f64]) -> Vec<i32> {
+    let total: f64 = weights.iter().sum();
+    let mut shares: Vec<i32> = weights.iter()
+        .map(|w| (sum_dim as f64 * w / total).floor() as i32)
+        .collect();
+
+    let leftover = sum_dim - shares.iter().sum::<i32>();
+    shares.iter_mut()
+        .take(leftover.max(0) as usize)
+        .for_each(|share| *share += 1);
+
+    shares
+}
+
+/// Like `apportion`, but no share may be smaller than `min`. Shares that would
+/// be are pinned at `min`, and the others divide what remains. This is 
+/// impossible if there is not even `min` for every share.
+fn apportion_min(sum_dim: i32, weights: &[f64], min: i32) -> Option<Vec<i32>> {
+    if min * weights.len() as i32 > sum_dim { return None }
+
+    let mut pinned = vec![false; weights.len()];
+    loop {
+        // Only the shares that are not pinned get any of the pixels left 
+        // over by rounding, the others are set to `min` regardless
+        let free: Vec<f64> = weights.iter().zip(&pinned)
+            .filter(|(_, p)| !**p)
+            .map(|(w, _)| *w)
+            .collect();
+        let num_pinned = pinned.iter().filter(|p| **p).count() as i32;
+        let mut free_shares = apportion(sum_dim - min * num_pinned, &free)
+            .into_iter();
+        let shares: Vec<i32> = pinned.iter()
+            .map(|p| if *p { min } else { free_shares.next().unwrap() })
+            .collect();
+
+        let mut done = true;
+        for (share, p) in shares.iter().zip(pinned.iter_mut()) {
+            if !*p && *share < min {
+                *p = true;
+                done = false;
+            }
+        }
+        if done { return Some(shares) }
+    }
+}
+
+/// A single resize of a child along its parent's split axis
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ResizeOp {
+    pub con_id: i64,
+    /// The layout of the parent, which determines the axis
+    pub layout: NodeLayout,
+    /// "grow", "shrink", or "set" for an absolute size
+    pub change: &'static str,
+    /// "right" or "down" for relative resizes, "width" or "height" for 
+    /// absolute ones
+    pub dir: &'static str,
+    /// The change in size, or the new size for absolute resizes
+    pub px: i32,
+    /// The new size in percentage points of the parent, if the resize is to
+    /// be expressed as such
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ppt: Option<i32>,
+}
+
+impl ResizeOp {
+    /// The command performing this resize, in a container whose size along 
+    /// `dir` is `parent_dim`
+    fn command(&self, wm: Wm, parent_dim: i32) -> String {
+        let ResizeOp { con_id, change, dir, px, ppt, .. } = self;
+
+        if let Some(ppt) = ppt {
+            return format!("[con_id={con_id}] resize {change} {dir} {ppt} ppt");
+        }
+
+        let cmd = format!("[con_id={con_id}] resize {change} {dir} {px} px");
+
+        match wm {
+            Wm::Sway if *change == "set" => {
+                let (width, height) = self.size();
+                set_size_command(*con_id, width, height).unwrap()
+            },
+            Wm::Sway => cmd,
+            // Absolute sizes can not fall back to another unit
+            Wm::I3 if *change == "set" => {
+                let ppt = px * 100 / parent_dim.max(1);
+                format!("[con_id={con_id}] resize set {dir} {ppt} ppt")
+            },
+            // i3 only resizes tiled containers in ppt, so offer an equivalent
+            Wm::I3 => {
+                let ppt = (px * 100 / parent_dim.max(1)).max(1);
+                format!("{cmd} or {ppt} ppt")
+            },
+        }
+    }
+
+    /// The width and height an absolute resize sets, whichever it sets
+    fn size(&self) -> (Option<i32>, Option<i32>) {
+        match self.dir {
+            "width" => (Some(self.px), None),
+            _ => (None, Some(self.px)),
+        }
+    }
+}
+
+/// The command setting the width and height of the node with `con_id` to
+/// these many pixels, or only the one given. None if neither is.
+pub fn set_size_command(con_id: i64, width: Option<i32>, height: Option<i32>)
+-> Option<String> {
+    let sizes = match (width, height) {
+        (Some(w), Some(h)) => format!("width {w} px height {h} px"),
+        (Some(w), None) => format!("width {w} px"),
+        (None, Some(h)) => format!("height {h} px"),
+        (None, None) => return None,
+    };
+    Some(format!("[con_id={con_id}] resize set {sizes}"))
+}
+
+/// Set the width and height of the node with `con_id` in a single command,
+/// or only the one given. The result is whether the window manager could;
+/// the error whether the command got there at all.
+pub fn resize_node(conn: &mut impl TreeProvider, con_id: i64, 
+    width: Option<i32>, height: Option<i32>)
+-> Result<swayipc::Fallible<()>, AppError> {
+    let Some(cmd) = set_size_command(con_id, width, height) else {
+        return Ok(Ok(()))
+    };
+    let mut res = run_with_retry(conn, &cmd)?;
+    tracing::debug!("{cmd}: {res:?}");
+    Ok(res.pop().unwrap_or(Ok(())))
+}
+
+/// What balancing a tree did to it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceReport {
+    /// Every split container that was balanced, in the order they were
+    pub containers: Vec<ContainerReport>,
+    /// How many times the whole tree was balanced, see `Options::passes`
+    pub passes: usize,
+    /// How many floating windows were resized, see `balance_floating`
+    pub floating: usize,
+}
+
+/// What balancing did to a single container
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct ContainerReport {
+    pub con_id: i64,
+    pub children: usize,
+    /// The sum of all successful resizes
+    pub px_moved: i32,
+    /// How many batches of resizes it took to converge, 0 if it was already
+    /// balanced
+    pub iterations: usize,
+    /// Whether we gave up on converging because of `Options::timeout`
+    pub timed_out: bool,
+    /// How many resize commands were sent
+    pub commands: usize,
+    /// How many resizes were left out because of `Options::max_commands`
+    pub deferred: usize,
+    /// The children that were not at their size after all, if checked with
+    /// `Options::verify`
+    pub off_target: Vec<i64>,
+}
+
+impl BalanceReport {
+    pub fn px_moved(&self) -> i32 {
+        self.containers.iter().map(|c| c.px_moved).sum()
+    }
+
+    /// Whether anything was resized at all
+    pub fn changed(&self) -> bool {
+        self.px_moved() > 0 || self.floating > 0
+    }
+
+    pub fn commands(&self) -> usize {
+        self.containers.iter().map(|c| c.commands).sum()
+    }
+
+    pub fn deferred(&self) -> usize {
+        self.containers.iter().map(|c| c.deferred).sum()
+    }
+
+    /// The windows that were not at their size after all, see 
+    /// `Options::verify`
+    pub fn off_target(&self) -> Vec<i64> {
+        self.containers.iter().flat_map(|c| c.off_target.iter().copied())
+            .collect()
+    }
+
+    /// Add what another pass over the same tree did
+    fn merge(&mut self, pass: BalanceReport) {
+        for c in pass.containers {
+            match self.containers.iter_mut().find(|o| o.con_id == c.con_id) {
+                Some(o) => {
+                    o.px_moved += c.px_moved;
+                    o.iterations += c.iterations;
+                    o.commands += c.commands;
+                    o.deferred = c.deferred;
+                    o.off_target = c.off_target;
+                    o.timed_out = c.timed_out;
+                },
+                None => self.containers.push(c),
+            }
+        }
+        self.passes += 1;
+    }
+}
+
+impl std::fmt::Display for BalanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let resized = self.containers.iter().filter(|c| c.px_moved > 0).count();
+        write!(f, "Balanced {resized} of {} containers, moved {}px",
+            self.containers.len(), self.px_moved())?;
+        if self.passes > 1 {
+            write!(f, " in {} passes", self.passes)?;
+        }
+        if self.deferred() > 0 {
+            write!(f, ", deferred {} resizes", self.deferred())?;
+        }
+        if self.floating > 0 {
+            write!(f, ", resized {} floating windows", self.floating)?;
+        }
+        match self.off_target().as_slice() {
+            [] => Ok(()),
+            ids => write!(f, ", {} windows off target {ids:?}", ids.len()),
+        }
+    }
+}
+
+/// Give every floating window below `root` the average size of all of them.
+/// They do not share an axis like tiled windows do, so each is simply set to
+/// that size, and stays where it is. Returns how many were resized.
+pub fn balance_floating(conn: &mut impl TreeProvider, root: &Node, 
+    opts: &Options) -> Result<usize, AppError>
+{
+    let mut floating = Vec::new();
+    bfs_visit(root, |n, _| floating.extend(n.floating_nodes.iter()));
+    if floating.len() < 2 { return Ok(0) }
+
+    let n = floating.len() as i32;
+    let width = floating.iter().map(|f| f.rect.width).sum::<i32>() / n;
+    let height = floating.iter().map(|f| f.rect.height).sum::<i32>() / n;
+
+    let cmds: Vec<String> = floating.iter()
+        .filter(|f| (f.rect.width - width).abs() > opts.tolerance
+            || (f.rect.height - height).abs() > opts.tolerance)
+        .filter_map(|f| set_size_command(f.id, Some(width), Some(height)))
+        .collect();
+    if cmds.is_empty() { return Ok(0) }
+
+    if opts.dry_run {
+        cmds.iter().for_each(output::result);
+        return Ok(cmds.len());
+    }
+
+    let res = run_with_retry(conn, &cmds.join("; "))?;
+    for (cmd, r) in cmds.iter().zip(&res) {
+        tracing::debug!("{cmd}: {r:?}");
+    }
+    Ok(res.iter().filter(|r| r.is_ok()).count())
+}
+
+/// How many rounds of adjusting leaves `balance_aspect` goes through at most.
+/// Every leaf changing the others means it need not settle by itself.
+const ASPECT_ROUNDS: usize = 10;
+
+/// Resize every tiled window below `root` towards the aspect ratio `aspect`
+/// (width over height), instead of balancing them. Each is set to the size
+/// along the axis furthest off that it would have at that ratio and its 
+/// current area, leaving the other to its container. Returns how many 
+/// resizes succeeded.
+pub fn balance_aspect(conn: &mut impl TreeProvider, root: &Node, 
+    opts: &Options, aspect: f64) -> Result<usize, AppError>
+{
+    let mut resized = 0;
+
+    for round in 0..ASPECT_ROUNDS {
+        let cur = conn.get_node(root.id)?;
+        let leaves = bfs_collect(&cur, |n| n.nodes.is_empty() 
+            && n.node_type == NodeType::Con);
+
+        let cmds: Vec<String> = leaves.iter()
+            .filter_map(|leaf| {
+                // Windows on a disabled output have no size, and so no ratio
+                // to correct. Going by it anyway would set them to 0.
+                if leaf.rect.width <= 0 || leaf.rect.height <= 0 { return None }
+                let (w, h) = (leaf.rect.width as f64, leaf.rect.height as f64);
+                let width = (w * h * aspect).sqrt().round() as i32;
+                let height = (w * h / aspect).sqrt().round() as i32;
+
+                // Too wide is best fixed by narrowing, too tall by lowering
+                let (set, off) = match w / h > aspect {
+                    true => ((Some(width), None), leaf.rect.width - width),
+                    false => ((None, Some(height)), leaf.rect.height - height),
+                };
+                if off.abs() <= opts.tolerance { return None }
+                set_size_command(leaf.id, set.0, set.1)
+            })
+            .collect();
+        if cmds.is_empty() { return Ok(resized) }
+
+        if opts.dry_run {
+            cmds.iter().for_each(output::result);
+            return Ok(cmds.len());
+        }
+
+        tracing::info!("aspect round {round}: {} windows off", cmds.len());
+        let res = run_with_retry(conn, &cmds.join("; "))?;
+        for (cmd, r) in cmds.iter().zip(&res) {
+            tracing::debug!("{cmd}: {r:?}");
+        }
+
+        let done = res.iter().filter(|r| r.is_ok()).count();
+        // Nothing could be resized, so the next round would not either
+        if done == 0 { return Ok(resized) }
+        resized += done;
+    }
+
+    tracing::warn!("not at aspect ratio {aspect} after {ASPECT_ROUNDS} rounds");
+    Ok(resized)
+}
+
+/// How far the children of a container are from the sizes balancing would
+/// give them
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Imbalance {
+    pub con_id: i64,
+    pub layout: NodeLayout,
+    pub children: usize,
+    /// The largest difference between the size of a child and its target, as
+    /// a percentage of that target
+    pub percent: f64,
+}
+
+/// How imbalanced every container below `root` is that balancing it would
+/// resize, parents before their children. Nothing is resized.
+pub fn imbalance(root: &Node, opts: &Options) -> Vec<Imbalance> {
+    let mut found = Vec::new();
+    let skipped = skipped_ids(root);
+
+    bfs_visit(root, |n, depth| {
+        if opts.depth.is_some_and(|max| depth > max) { return }
+        if skipped.contains(&n.id) { return }
+        if n.nodes.len() < opts.min_children.max(2) { return }
+        // Nothing is resized, so the targets may as well be those of the
+        // layout it is marked with
+        let layout = layout_of(n, true);
+        if !opts.splits.allows(layout) { return }
+        if !opts.containers.allows(n.node_type) { return }
+
+        // Like in `balance_container`, what is above the workspaces is not
+        // ours to resize
+        let above_workspaces = 
+            matches!(n.node_type, NodeType::Root | NodeType::Output)
+            || n.nodes.iter().any(|c| c.node_type == NodeType::Workspace);
+        if above_workspaces { return }
+
+        let Some(axis) = Axis::of(layout) else { return };
+        let min = axis.min(opts);
+
+        let sum_dim: i32 = n.nodes.iter().map(|c| axis.dim(c)).sum();
+        let Some(desired) = targets(&n.nodes, axis, sum_dim, min, depth, opts)
+        else { return };
+
+        let percent = n.nodes.iter().zip(&desired)
+            .map(|(c, d)| (axis.dim(c) - d).abs() as f64 * 100.0 / *d.max(&1) as f64)
+            .fold(0.0, f64::max);
+        found.push(Imbalance { 
+            con_id: n.id, 
+            layout, 
+            children: n.nodes.len(), 
+            percent,
+        });
+    });
+
+    found
+}
+
+/// Queue the children of the container at `depth` for balancing, unless they
+/// are deeper than we were asked to go
+fn enqueue(q: &mut VecDeque<(i64, usize)>, container: &Node, depth: usize,
+    opts: &Options) 
+{
+    if opts.depth.is_some_and(|max| depth >= max) { return }
+    q.extend(container.nodes.iter().map(|n| (n.id, depth + 1)));
+}
+
+/// Balance the tree below `root` with the default options
+pub fn balance(conn: &mut impl TreeProvider, root: &Node) 
+-> Result<BalanceReport, AppError> {
+    balance_with(conn, root, &Options::default())
+}
+
+/// Balance the tree below `root`
+/// Only tiled children are considered, floating windows are left alone.
+pub fn balance_with(conn: &mut impl TreeProvider, root: &Node, opts: &Options)
+-> Result<BalanceReport, AppError> {
+    let mut conn = CachingProvider::new(conn);
+    in_passes(opts, |opts| run_balance(&mut conn, root, 0, opts, None))
+}
+
+/// Run `pass` up to `Options::passes` times, until one has nothing left to 
+/// resize. Resizing one container can make the window manager move things 
+/// in others, which only a new pass over them notices. Every pass gets the
+/// options with what is left of `Options::max_commands`.
+fn in_passes(opts: &Options, mut pass: impl FnMut(&Options) 
+    -> Result<BalanceReport, AppError>) -> Result<BalanceReport, AppError>
+{
+    let mut report = BalanceReport::default();
+
+    for _ in 0..opts.passes.max(1) {
+        let left = opts.max_commands
+            .map(|max| max.saturating_sub(report.commands()));
+        let done = pass(&Options { max_commands: left, ..opts.clone() })?;
+        let settled = done.commands() == 0;
+        report.merge(done);
+        // A dry run changes nothing, so every pass would be the same
+        if settled || opts.dry_run { break }
+    }
+    Ok(report)
+}
+
+/// Balance each of `roots` with `balance`, carrying on past the ones that
+/// fail. Like passes, every root gets the options with what the ones before
+/// it left of `Options::max_commands`, and once that is used up the rest are
+/// left alone. Returns what was done to all of them together, and how many
+/// failed.
+pub fn balance_each(roots: &[&Node], opts: &Options, 
+    mut balance: impl FnMut(&Node, &Options) -> Result<BalanceReport, AppError>)
+-> (BalanceReport, usize)
+{
+    let mut total = BalanceReport::default();
+    let mut failed = 0;
+
+    for root in roots {
+        let left = opts.max_commands
+            .map(|max| max.saturating_sub(total.commands()));
+        if left == Some(0) {
+            tracing::info!("out of commands, leaving {} alone", root.id);
+            break
+        }
+
+        match balance(root, &Options { max_commands: left, ..opts.clone() }) {
+            Ok(report) => {
+                total.passes = total.passes.max(report.passes);
+                total.containers.extend(report.containers);
+            },
+            Err(_) => failed += 1,
+        }
+    }
+
+    (total, failed)
+}
+
+/// The resizes balancing the tree below `root` would start with, without
+/// performing any of them
+pub fn plan(conn: &mut impl TreeProvider, root: &Node, opts: &Options)
+-> Result<Vec<ResizeOp>, AppError> {
+    let mut ops = Vec::new();
+    let opts = Options { dry_run: true, ..opts.clone() };
+    run_balance(&mut CachingProvider::new(conn), root, 0, &opts, Some(&mut ops))?;
+    Ok(ops)
+}
+
+/// How balancing would change the sizes of the children of a container
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeDiff {
+    pub con_id: i64,
+    pub layout: NodeLayout,
+    /// The id of every child, with its size along the axis of the container
+    /// now and after balancing
+    pub sizes: Vec<(i64, i32, i32)>,
+}
+
+/// How balancing would change the sizes of the children of every container
+/// below `root` it resizes anything in, without resizing anything. The sizes
+/// are found by balancing a copy of `tree` in memory, so the containers 
+/// below ones that are resized get sizes to match.
+pub fn diff(tree: &Node, root: &Node, opts: &Options)
+-> Result<Vec<SizeDiff>, AppError> {
+    // Resizing in memory is not resizing for real, so the copy is balanced
+    // as the layouts it is marked with, like in a dry run
+    diff_as(tree, root, opts, true)
+}
+
+/// `diff`, with the containers balanced as the layout they are marked with
+/// if `marked`, see `layout_of`
+fn diff_as(tree: &Node, root: &Node, opts: &Options, marked: bool)
+-> Result<Vec<SizeDiff>, AppError> {
+    let mut copy = tree.clone();
+    if marked { use_marked_layouts(&mut copy) }
+    let mut mock = provider::MockProvider::new(copy);
+    let opts = Options { dry_run: false, ..opts.clone() };
+    balance_with(&mut mock, root, &opts)?;
+    let balanced = find_by_id(mock.tree(), root.id).ok_or(AppError::NodeGone)?;
+
+    let mut diffs = Vec::new();
+    bfs_visit(balanced, |n, _| {
+        let Some(axis) = Axis::of(n.layout) else { return };
+        let sizes: Vec<(i64, i32, i32)> = n.nodes.iter()
+            .map(|c| {
+                let before = find_by_id(tree, c.id).unwrap_or(c);
+                (c.id, axis.dim(before), axis.dim(c))
+            })
+            .collect();
+
+        if sizes.iter().any(|(_, before, after)| before != after) {
+            diffs.push(SizeDiff { con_id: n.id, layout: n.layout, sizes });
+        }
+    });
+    Ok(diffs)
+}
+
+/// Give every node in the tree below `node` the layout it is marked with, see
+/// `layout_of`
+fn use_marked_layouts(node: &mut Node) {
+    node.layout = layout_of(node, true);
+    node.nodes.iter_mut().for_each(use_marked_layouts);
+}
+
+/// The commands setting every window below `root` to the size balancing
+/// would give it, child by child within each container, without running any
+/// of them. See `diff`.
+pub fn script(tree: &Node, root: &Node, opts: &Options)
+-> Result<Vec<String>, AppError> {
+    let mut cmds = Vec::new();
+
+    // The commands are there to be run, and sway resizes a split along its
+    // own axis, so the layouts are the real ones like when balancing
+    for d in diff_as(tree, root, opts, false)? {
+        for (id, before, after) in d.sizes {
+            if before == after { continue }
+            let (width, height) = match d.layout {
+                NodeLayout::SplitH => (Some(after), None),
+                _ => (None, Some(after)),
+            };
+            cmds.extend(set_size_command(id, width, height));
+        }
+    }
+    Ok(cmds)
+}
+
+/// Balance the tree below `root` like `balance_with`, but the subtrees below
+/// its children on up to `jobs` threads at once. Once `root` itself is
+/// balanced its children keep their size, so their subtrees do not interact.
+/// A connection can not be shared between threads, so every thread opens its
+/// own with `connect`.
+pub fn balance_parallel<F, P>(connect: F, root: &Node, opts: &Options,
+    jobs: usize) -> Result<BalanceReport, AppError>
+where F: Fn() -> Result<P, AppError> + Sync, P: TreeProvider
+{
+    in_passes(opts, |opts| parallel_pass(&connect, root, opts, jobs))
+}
+
+/// A single pass of `balance_parallel`
+fn parallel_pass<F, P>(connect: &F, root: &Node, opts: &Options, jobs: usize)
+-> Result<BalanceReport, AppError>
+where F: Fn() -> Result<P, AppError> + Sync, P: TreeProvider
+{
+    let mut conn = CachingProvider::new(connect()?);
+    let top_opts = Options { depth: Some(0), ..opts.clone() };
+    let mut report = run_balance(&mut conn, root, 0, &top_opts, None)?;
+
+    if opts.depth == Some(0) || is_skipped(root) { return Ok(report) }
+
+    // Partition the subtrees round robin, every one stays on a single thread
+    // so the resizes within a container keep their order
+    let subtrees: Vec<i64> = conn.get_node(root.id)?
+        .nodes.iter().map(|n| n.id).collect();
+    let jobs = jobs.clamp(1, subtrees.len().max(1));
+    let partitions: Vec<Vec<i64>> = (0..jobs)
+        .map(|j| subtrees.iter().skip(j).step_by(jobs).copied().collect())
+        .collect();
+
+    let results: Vec<Result<BalanceReport, AppError>> = std::thread::scope(|s| {
+        let workers: Vec<_> = partitions.iter()
+            .map(|ids| s.spawn(move || {
+                let mut conn = CachingProvider::new(connect()?);
+                let mut report = BalanceReport::default();
+                for id in ids {
+                    let subtree = conn.get_node(*id)?;
+                    // Still one level below `root`, which is what the ratios
+                    // and depth limits go by
+                    let sub = run_balance(&mut conn, &subtree, 1, opts, None)?;
+                    report.containers.extend(sub.containers);
+                }
+                Ok(report)
+            }))
+            .collect();
+
+        workers.into_iter()
+            .map(|w| w.join().expect("balancing thread panicked"))
+            .collect()
+    });
+
+    for result in results {
+        report.containers.extend(result?.containers);
+    }
+    Ok(report)
+}
+
+/// How long to wait before each retry of commands that did not get through
+const BACKOFF: [Duration; 3] = [
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(200),
+];
+
+/// Run `cmds`, retrying with `BACKOFF` if they do not get through to the
+/// window manager at all. Commands that get through but fail are not retried.
+fn run_with_retry(conn: &mut impl TreeProvider, cmds: &str)
+-> Result<Vec<swayipc::Fallible<()>>, AppError> {
+    let mut backoff = BACKOFF.iter();
+    loop {
+        match (conn.run_command(cmds), backoff.next()) {
+            (Ok(res), _) => return Ok(res),
+            (Err(_), Some(wait)) => std::thread::sleep(*wait),
+            (Err(_), None) => return Err(AppError::Resize),
+        }
+    }
+}
+
+/// Whether a resize failed because there was no room to resize into
+fn is_stuck(res: &swayipc::Fallible<()>) -> bool {
+    matches!(res, Err(CommandParse(e)) if e == "Cannot resize any further")
+}
+
+/// Retry the resizes in `ops` that got stuck towards the next sibling towards
+/// the previous one instead, replacing their results in `res`. A child right
+/// before one that can not give up any more space may still take it from the
+/// other side. Returns whether any were retried: that moves the edge before
+/// the child rather than the one after it, so the sizes are not yet those
+/// that were asked for.
+fn resize_other_way(conn: &mut impl TreeProvider, ops: &[ResizeOp],
+    res: &mut [swayipc::Fallible<()>], wm: Wm, parent_dim: i32)
+-> Result<bool, AppError> {
+    let stuck: Vec<(usize, ResizeOp)> = ops.iter().zip(res.iter()).enumerate()
+        .filter(|(_, (_, r))| is_stuck(r))
+        .filter_map(|(i, (op, _))| {
+            // Sets have no direction to turn around
+            let axis = Axis::of(op.layout)?;
+            if op.dir != axis.grow_dir() { return None }
+            Some((i, ResizeOp { dir: axis.shrink_dir(), ..op.clone() }))
+        })
+        .collect();
+    if stuck.is_empty() { return Ok(false) }
+
+    let cmds: Vec<String> = stuck.iter()
+        .map(|(_, op)| op.command(wm, parent_dim))
+        .collect();
+    let retried = run_with_retry(conn, &cmds.join("; "))?;
+    for ((i, _), r) in stuck.iter().zip(retried) {
+        res[*i] = r;
+    }
+    Ok(true)
+}
+
+/// The sizes the children of `cur` should have to line up with those of its
+/// siblings, if they form a grid together: a split of splits along the other
+/// axis, all with as many children. Each row gets the average of what every
+/// column on its own would give it. None if `cur` is not part of a grid.
+fn grid_targets(conn: &mut impl TreeProvider, cur: &Node, axis: Axis, 
+    min: i32, depth: usize, opts: &Options)
+-> Result<Option<Vec<i32>>, AppError> {
+    let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
+    let Some(parent) = parent_of(&tree, cur.id) else { return Ok(None) };
+
+    let splits = matches!(parent.layout, NodeLayout::SplitH | NodeLayout::SplitV);
+    let is_grid = splits && parent.layout != cur.layout
+        && parent.nodes.iter()
+            .all(|c| c.layout == cur.layout && c.nodes.len() == cur.nodes.len());
+    if !is_grid { return Ok(None) }
+
+    let columns: Vec<Vec<i32>> = parent.nodes.iter()
+        .filter_map(|c| {
+            let sum_dim = c.nodes.iter().map(|n| axis.dim(n)).sum();
+            targets(&c.nodes, axis, sum_dim, min, depth, opts)
+        })
+        .collect();
+    if columns.is_empty() { return Ok(None) }
+
+    let n = columns.len() as i32;
+    let mut rows: Vec<i32> = (0..cur.nodes.len())
+        .map(|i| columns.iter().map(|c| c[i]).sum::<i32>() / n)
+        .collect();
+    // Rounding down leaves a few pixels, which the last row takes
+    let sum_dim: i32 = cur.nodes.iter().map(|n| axis.dim(n)).sum();
+    let left = sum_dim - rows.iter().sum::<i32>();
+    *rows.last_mut().unwrap() += left;
+
+    Ok(Some(rows))
+}
+
+/// Remove `cur`, a container with a single child, and put the child in its
+/// place. Not being able to is only worth a warning. In a dry run the command
+/// is printed if `print`.
+fn flatten(conn: &mut impl TreeProvider, cur: &Node, opts: &Options, 
+    print: bool) -> Result<(), AppError>
+{
+    // Flattens the parent of the container it targets, if it is the only one
+    let cmd = format!("[con_id={}] split none", cur.nodes[0].id);
+    if opts.dry_run {
+        if print { output::result(&cmd) }
+        return Ok(())
+    }
+
+    let res = run_with_retry(conn, &cmd)?;
+    tracing::debug!("{cmd}: {res:?}");
+    if let Some(Err(e)) = res.first() {
+        tracing::warn!("could not flatten container {}: {e}", cur.id);
+    }
+    Ok(())
+}
+
+/// The scale of the output the node with `id` is on, if the options are in
+/// device pixels
+fn output_scale(conn: &mut impl TreeProvider, id: i64, opts: &Options)
+-> Result<Option<f64>, AppError> {
+    if opts.device_scales.is_empty() { return Ok(None) }
+
+    let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
+    let scale = path_to(&tree, id).iter().rev()
+        .find(|n| n.node_type == NodeType::Output)
+        .and_then(|output| output.name.as_ref())
+        .and_then(|name| opts.device_scales.get(name))
+        .copied();
+    Ok(scale)
+}
+
+/// Balance the tree below `root`, which is `depth` levels below where
+/// balancing started. In a dry run, the resizes are collected in `plan` if
+/// given, and printed otherwise.
+fn run_balance(conn: &mut impl TreeProvider, root: &Node, depth: usize,
+    opts: &Options, mut plan: Option<&mut Vec<ResizeOp>>)
+-> Result<BalanceReport, AppError> 
+{
+    let mut report = BalanceReport::default();
+    // One container failing should not keep the others from being balanced
+    let mut failed = Vec::new();
+
+    // Like a workspace without windows, or with only floating ones. That is
+    // not an error, and not worth asking the window manager about.
+    if root.nodes.is_empty() {
+        tracing::warn!("nothing to balance, {} holds no tiled windows", root.id);
+        return Ok(report)
+    }
+
+    // Balancing the innermost containers first means the outer ones do not
+    // get their sizes undone by them
+    if opts.bottom_up {
+        let order = containers(&conn.get_node(root.id)?, depth, opts);
+        for (id, depth) in order.into_iter().rev() {
+            let res = balance_container(conn, id, depth, opts, 
+                plan.as_deref_mut(), &mut report);
+            if let Err(e) = res { failed.push((id, e)) }
+        }
+        return partial(report, failed);
+    }
+
+    // Containers to balance, along with their depth
+    let mut q: VecDeque<(i64, usize)> = VecDeque::from(vec![(root.id, depth)]);
+
+    while let Some((cur_id, depth)) = q.pop_front() {
+        tracing::trace!("queue: [{cur_id}] {q:?}");
+
+        // Neither resized nor descended into. Served from the cache, so this
+        // costs no ipc of its own.
+        if conn.get_node(cur_id).is_ok_and(|n| is_skipped(&n)) {
+            tracing::debug!("skipping marked container {cur_id}");
+            continue
+        }
+
+        match balance_container(conn, cur_id, depth, opts, 
+            plan.as_deref_mut(), &mut report) {
+            Ok(Some(cur)) => enqueue(&mut q, &cur, depth, opts),
+            Ok(None) => {},
+            Err(e) => failed.push((cur_id, e)),
+        }
+    }
+
+    partial(report, failed)
+}
+
+/// `report`, unless some containers `failed`
+fn partial(report: BalanceReport, failed: Vec<(i64, AppError)>)
+-> Result<BalanceReport, AppError> {
+    match failed.is_empty() {
+        true => Ok(report),
+        false => Err(AppError::Partial(failed)),
+    }
+}
+
+/// Every container below `root` (and `root` itself) that may need balancing,
+/// along with its depth, `root` being at `base`. Parents come before their
+/// children.
+fn containers(root: &Node, base: usize, opts: &Options) -> Vec<(i64, usize)> {
+    let mut found = Vec::new();
+    let skipped = skipped_ids(root);
+    bfs_visit(root, |n, depth| {
+        let depth = base + depth;
+        let too_deep = opts.depth.is_some_and(|max| depth > max);
+        if n.nodes.is_empty() || too_deep || skipped.contains(&n.id) { return }
+        found.push((n.id, depth));
+    });
+    found
+}
+
+/// Balance the children of the container with `cur_id`, adding how that went
+/// to `report`. Returns the container as it is afterwards if the containers
+/// below it should be balanced too.
+fn balance_container(conn: &mut impl TreeProvider, cur_id: i64, depth: usize,
+    opts: &Options, mut plan: Option<&mut Vec<ResizeOp>>,
+    report: &mut BalanceReport) -> Result<Option<Node>, AppError>
+{
+    // Snapshot of the container, refreshed before every retry
+    let mut cur = conn.get_node(cur_id)?;
+    let _span = tracing::info_span!("container", con_id = cur_id, 
+        layout = ?cur.layout).entered();
+    if cur.nodes.is_empty() { return Ok(None) }
+    if cur.name.as_deref() == Some("__i3_scratch") { return Ok(None) }
+
+    // Outputs, and the workspaces on them, are laid out by the window
+    // manager. Only what is on the workspaces is ours to resize.
+    let above_workspaces = 
+        matches!(cur.node_type, NodeType::Root | NodeType::Output)
+        || cur.nodes.iter().any(|n| n.node_type == NodeType::Workspace);
+    if above_workspaces {
+        return Ok(Some(cur))
+    }
+
+    // Left behind by closing windows. With a single child there is nothing
+    // to divide, but there may be below it.
+    if cur.nodes.len() == 1 {
+        if opts.flatten && cur.node_type == NodeType::Con {
+            flatten(conn, &cur, opts, plan.is_none())?;
+        }
+        return Ok(Some(cur))
+    }
+
+    // Sway speaks logical pixels, whatever the options are in
+    let logical;
+    let opts = match output_scale(conn, cur_id, opts)? {
+        Some(scale) => {
+            logical = opts.in_logical_pixels(scale);
+            &logical
+        },
+        None => opts,
+    };
+
+    let flip = match opts.auto_orient {
+        true => fitting_layout(&cur),
+        false => None,
+    };
+    if let Some(layout) = flip {
+        // `layout` applies to the parent of the container it targets, and
+        // `split` would wrap the container in a new one instead
+        let cmd = format!("[con_id={}] layout {layout}", cur.nodes[0].id);
+
+        // The sizes after flipping can not be known without flipping
+        if opts.dry_run {
+            if plan.is_none() { output::result(&cmd) }
+            return Ok(Some(cur))
+        }
+
+        let res = run_with_retry(conn, &cmd)?;
+        tracing::debug!("{cmd}: {res:?}");
+        match res.first() {
+            Some(Err(e)) => tracing::warn!("could not flip container {cur_id}: {e}"),
+            _ => cur = conn.get_node(cur_id)?,
+        }
+    }
+
+    let layout = layout_of(&cur, opts.dry_run);
+    if layout_of(&cur, true) != layout {
+        tracing::warn!("ignoring the balance-layout mark of container {cur_id} \
+            outside a dry run");
+    }
+    let axis = match (Axis::of(layout), layout) {
+        (Some(axis), _) => axis,
+        // Children of these all share the full rect, but may themselves
+        // contain splits that need balancing. Nodes without a layout of 
+        // their own do not divide anything either.
+        (None, NodeLayout::Tabbed | NodeLayout::Stacked | NodeLayout::None) => {
+            return Ok(Some(cur))
+        },
+        // Dock areas and the like hold nothing we should touch
+        (None, _) => return Ok(None),
+    };
+    let min = axis.min(opts);
+
+    // Not ours to resize, but there may be splits of the right kind below
+    let allowed = opts.splits.allows(layout) 
+        && opts.containers.allows(cur.node_type);
+    if !allowed || cur.nodes.len() < opts.min_children {
+        return Ok(Some(cur))
+    }
+
+    // Trees read in the middle of switching workspaces can have children
+    // without a size, and balancing those would send resizes of any size
+    if !cur.nodes.iter().all(|c| axis.dim(c) > 0) {
+        tracing::warn!("container {cur_id} has children without a size, \
+            fetching it again");
+        conn.invalidate();
+        cur = conn.get_node(cur_id)?;
+        if !cur.nodes.iter().all(|c| axis.dim(c) > 0) {
+            tracing::warn!("leaving container {cur_id} alone, its children \
+                still have no size");
+            return Ok(None)
+        }
+    }
+
+    let sum_dim: i32 = cur.nodes.iter().map(|c| axis.dim(c)).sum();
+    let Some(desired_dims) = targets(&cur.nodes, axis, sum_dim, min, depth, opts) 
+    else {
+        return Ok(Some(cur))
+    };
+    let desired_dims = match opts.grid_align {
+        true => grid_targets(conn, &cur, axis, min, depth, opts)?
+            .unwrap_or(desired_dims),
+        false => desired_dims,
+    };
+    let child_ids: Vec<i64> = cur.nodes.iter().map(|n| n.id).collect();
+
+    // The rects of the children exclude the gaps between them, percentages
+    // are of the size of the whole container
+    let parent_dim = sum_dim + (child_ids.len() as i32 - 1) * opts.gaps;
+
+    tracing::info!("sum {sum_dim}, desired {desired_dims:?}");
+
+    let mut stats = ContainerReport { 
+        con_id: cur_id, 
+        children: child_ids.len(),
+        ..Default::default()
+    };
+
+    // This should happen at most (\Sum_{k=1}^{num_of_children} k) times
+    let n = child_ids.len() as f64;
+    let max_iterations = (0.5 * n * (n + 1.0)).round() as usize;
+    let started = Instant::now();
+    // Whether any resize of the last iteration went through
+    let mut progressed = true;
+    // Whether the sizes were already checked, and corrected, for `verify`
+    let mut corrected = false;
+
+    for i in 0..max_iterations {
+        // Loop until we were able to resize all children to the requested
+        // size. This may take multiple tries if there is not enough space
+        // in the adjacent container to grow into.
+        if opts.timeout.is_some_and(|t| started.elapsed() > t) {
+            tracing::warn!("container {cur_id} did not converge in time");
+            stats.timed_out = true;
+            break;
+        }
+        if i > 0 { cur = conn.get_node(cur_id)?; }
+
+        // Resizing a child towards `dir` takes the space from (or gives 
+        // it to) its next sibling, so each child has to make up for the
+        // error of all children before it, not just its own.
+        let mut ops = Vec::new();
+        let mut moved = Vec::new();
+        let mut error = 0;
+
+        // Once all except the last been resized, 
+        // the last one should already have the right size
+        let all_except_last = child_ids.iter().zip(&desired_dims)
+            .take(child_ids.len()-1);
+
+        for (child_id, desired_dim) in all_except_last {
+            let child = cur.nodes.iter()
+                .find(|n| n.id == *child_id)
+                .ok_or(AppError::NodeGone)?;
+
+            if opts.absolute && !opts.ppt {
+                let off = desired_dim - axis.dim(child);
+                if off.abs() <= opts.tolerance { continue } // Close enough
+                if off.abs() < opts.min_px_change { continue }
+                if opts.grow_only && off < 0 { continue }
+
+                ops.push(ResizeOp {
+                    con_id: *child_id,
+                    layout,
+                    change: "set",
+                    dir: axis.name(),
+                    px: *desired_dim,
+                    ppt: None,
+                });
+                moved.push(off.abs());
+                continue
+            }
+
+            // Absolute sizes do not depend on the siblings before
+            if opts.ppt {
+                let to_ppt = |px: i32| (px as f64 * 100.0 / parent_dim as f64)
+                    .round() as i32;
+                let ppt = to_ppt(*desired_dim);
+                if ppt == to_ppt(axis.dim(child)) { continue } // Close enough
+                // Percentages are rounded down to pixels again, so setting 
+                // it may not change anything at all
+                if ppt * parent_dim / 100 == axis.dim(child) { continue }
+                if opts.grow_only && *desired_dim < axis.dim(child) { continue }
+
+                ops.push(ResizeOp {
+                    con_id: *child_id,
+                    layout,
+                    change: "set",
+                    dir: axis.name(),
+                    px: *desired_dim,
+                    ppt: Some(ppt),
+                });
+                moved.push((desired_dim - axis.dim(child)).abs());
+                continue
+            }
+
+            error += desired_dim - axis.dim(child);
+            if error.abs() <= opts.tolerance { continue } // Close enough
+            // Not worth a command, but the children after it still are
+            if error.abs() < opts.min_px_change { continue }
+            // The edge would move back into this child. Leaving it where it
+            // is does not throw off the edges after it.
+            if opts.grow_only && error < 0 { continue }
+
+            ops.push(ResizeOp {
+                con_id: *child_id,
+                layout,
+                change: if error < 0 { "shrink" } else { "grow" },
+                dir: axis.grow_dir(),
+                px: error.abs(),
+                ppt: None,
+            });
+            moved.push(error.abs());
+        }
+        if ops.is_empty() { break }
+
+        // Out of budget, keep the resizes moving the most. Every resize puts
+        // a single edge in place, so leaving out some does not throw off the
+        // others.
+        let left = opts.max_commands
+            .map(|max| max.saturating_sub(report.commands() + stats.commands));
+        let over_budget = left.is_some_and(|left| ops.len() > left);
+        if let Some(left) = left.filter(|_| over_budget) {
+            let mut order: Vec<usize> = (0..ops.len()).collect();
+            order.sort_by_key(|&i| std::cmp::Reverse(moved[i]));
+            let keep: HashSet<usize> = order.into_iter().take(left).collect();
+
+            stats.deferred += ops.len() - left;
+            let mut i = 0..;
+            ops.retain(|_| keep.contains(&i.next().unwrap()));
+            let mut i = 0..;
+            moved.retain(|_| keep.contains(&i.next().unwrap()));
+            if ops.is_empty() { break }
+        }
+        // A set can get stuck on a sibling that another set makes room in
+        // later, so it may go through again. Without any of them going 
+        // through, setting the sizes again would not get them any closer.
+        let sets = opts.absolute || opts.ppt;
+        if sets && i > 0 && !progressed {
+            tracing::warn!("container {cur_id} is not at the sizes it was set to");
+            break;
+        }
+        stats.iterations += 1;
+        stats.commands += ops.len();
+
+        let cmds: Vec<String> = ops.iter()
+            .map(|op| op.command(opts.wm, parent_dim))
+            .collect();
+
+        // Pretend the commands succeeded, nothing will have changed
+        if opts.dry_run {
+            stats.px_moved += moved.iter().sum::<i32>();
+            match plan.as_deref_mut() {
+                Some(plan) => plan.extend(ops),
+                None => cmds.iter().for_each(output::result),
+            }
+            break;
+        }
+
+        // run_command returns a Result<Vec<Result<_,_>>,_>.
+        // The outermost result indicates whether executing the commands 
+        // went wrong in some way. The innermost vector of results
+        // indicates, for each command, the result of executing the 
+        // command. The outermost one only goes wrong if retrying does not
+        // help either. Sizes in pixels are set a window at a time, like 
+        // everywhere else they are, except in i3 which sets them in ppt.
+        let set_px = opts.absolute && !opts.ppt && opts.wm == Wm::Sway;
+        let mut res = match set_px {
+            true => ops.iter()
+                .map(|op| {
+                    let (width, height) = op.size();
+                    resize_node(conn, op.con_id, width, height)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            false => run_with_retry(conn, &cmds.join("; "))?,
+        };
+        let retried = resize_other_way(conn, &ops, &mut res, opts.wm, 
+            parent_dim)?;
+
+        for (op, r) in ops.iter().zip(&res) {
+            tracing::debug!(con_id = op.con_id, change = op.change, dir = op.dir,
+                px = op.px, result = ?r, "resize");
+        }
+
+        stats.px_moved += moved.iter().zip(&res)
+            .filter(|(_, r)| r.is_ok())
+            .map(|(px, _)| px)
+            .sum::<i32>();
+        progressed = res.iter().any(|r| r.is_ok());
+
+        // The innermost commands can only be of the "cannot resize" type
+        // any other error is unexpected and should propegate
+        let mut succeeded = !retried;
+        for r in res.iter().filter(|r| r.is_err()) {
+            match is_stuck(r) {
+                true => succeeded = false,
+                false => return Err(AppError::Resize),
+            }
+        }
+        // Setting a size takes from the next sibling, which may already 
+        // have been at its size, so sets are checked until none are left.
+        // What was left out would only be put off again.
+        let settled = succeeded && !sets;
+        // Check the sizes once more, in case sway clamped some of them
+        // without saying so
+        if settled && opts.verify && !corrected {
+            corrected = true;
+            continue
+        }
+        if settled || over_budget { break }
+    }
+
+    if opts.verify && !opts.dry_run {
+        cur = conn.get_node(cur_id)?;
+        stats.off_target = cur.nodes.iter().zip(&desired_dims)
+            .filter(|(c, d)| (axis.dim(c) - *d).abs() > opts.tolerance)
+            .map(|(c, _)| c.id)
+            .collect();
+        for id in &stats.off_target {
+            tracing::warn!("window {id} did not end up at its balanced size");
+        }
+    }
+    report.containers.push(stats);
+    Ok(Some(cur))
+}
@@ -1,164 +1,607 @@
-use std::collections::VecDeque;
-use swayipc::{Connection, Node, NodeLayout};
-use swayipc::Error::CommandParse;
-use clap::{Command, Arg};
-
-
-#[derive(Debug, thiserror::Error)]
-pub enum AppError {
-    #[error("Could not open a connection to sway") ]
-    Conn,
-    #[error("Could not get the node layout tree") ]
-    GetTree,
-    #[error("Could not get the workspaces") ]
-    GetWorkspaces,
-    #[error("Error issuing reize command") ]
-    Resize,
-    #[error("Node disappeared while running") ]
-    NodeGone,
-    #[error("Current focus could not be determined") ]
-    NoFocus,
-}
-
-
-/// Breadth first search for the first node for which `predicate` holds
-fn bfsearch<'a>(root: &'a Node, predicate: impl Fn(&'a Node) -> bool)
--> Option<&'a Node> 
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use swayipc::{Node, Workspace};
+use clap::ArgMatches;
+use sway_balance_workspace::{
+    balance_aspect, balance_each, balance_floating, balance_parallel,
+    balance_with, bfs_collect, bfs_find, connect, diff, find_by_id, 
+    find_marked, find_output, find_workspace, focused_workspace, 
+    focused_workspace_node, imbalance, parent_of, path_to, plan, script, 
+    top_focus, workspace_nodes, AppError, BalanceReport, Imbalance, Options,
+    SizeDiff,
+};
+use sway_balance_workspace::output;
+use sway_balance_workspace::provider::{
+    LiveProvider, MockProvider, Profile, TreeProvider,
+};
+use sway_balance_workspace::state;
+
+mod cli;
+mod config;
+
+use config::Config;
+mod notify;
+mod watch;
+
+
+/// Balance `root`, on several threads if the arguments ask for it
+fn balance_one(conn: &mut LiveProvider, socket: Option<&Path>, root: &Node,
+    opts: &Options, jobs: usize) -> Result<BalanceReport, AppError>
 {
-    let mut q = VecDeque::from(vec![root]);
+    let connect = || connect(socket).map(LiveProvider::from);
+    match jobs {
+        1 => balance_with(conn, root, opts),
+        _ => balance_parallel(connect, root, opts, jobs),
+    }
+}
 
-    while let Some(n) = q.pop_front() {
-        if predicate(n) { 
-            return Some(n) 
+/// Balance several workspaces, carrying on past the ones that fail. Returns
+/// what was done to all of them together, and how many failed.
+fn balance_all(conn: &mut LiveProvider, socket: Option<&Path>,
+    workspaces: &[&Node], opts: &Options, jobs: usize) -> (BalanceReport, usize)
+{
+    balance_each(workspaces, opts, |workspace, opts| {
+        let name = workspace.name.as_deref().unwrap_or("?");
+        let res = balance_one(conn, socket, workspace, opts, jobs);
+        match &res {
+            Ok(report) => tracing::info!("{name}: {report}"),
+            Err(e) => output::error(format_args!("{name}: {e}")),
         }
+        res
+    })
+}
 
-        q.extend(n.nodes.iter());
-    };
+/// Print the resizes balancing `roots` would start with as one json array
+fn print_plan(conn: &mut LiveProvider, roots: &[&Node], opts: &Options)
+-> Result<(), AppError> {
+    let mut ops = Vec::new();
+    for root in roots {
+        ops.extend(plan(conn, root, opts)?);
+    }
 
-    None // Never found
+    output::json(&ops);
+    Ok(())
 }
 
-/// Find a node with `id` in some (sub-)tree
-fn find_by_id(root: &Node, id: i64) -> Option<&Node> {
-    bfsearch(root, |n| n.id == id)
+/// Print a single json line describing a balance the watch did after
+/// `trigger`
+fn print_event(trigger: &str, roots: &[&Node], report: &BalanceReport) {
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64());
+    let workspace: Vec<&str> = roots.iter()
+        .map(|n| n.name.as_deref().unwrap_or("?"))
+        .collect();
+
+    output::event(serde_json::json!({
+        "ts": ts,
+        "workspace": workspace.join(","),
+        "containers": report.containers.len(),
+        "pixels_moved": report.px_moved(),
+        "triggered_by": trigger,
+    }));
 }
 
-/// Find the highest level node that is focused. 
-/// This should be the "largest" container that is focused
-fn top_focus(root: &Node) -> Option<&Node> {
-    bfsearch(root, |n| n.focused)
+/// Print `report` as a single json object, along with its totals and how
+/// long balancing took
+fn print_report(report: &BalanceReport, elapsed: Duration) {
+    output::event(serde_json::json!({
+        "containers": report.containers,
+        "passes": report.passes,
+        "commands": report.commands(),
+        "deferred": report.deferred(),
+        "floating": report.floating,
+        "pixels_moved": report.px_moved(),
+        "elapsed_ms": elapsed.as_millis() as u64,
+    }));
 }
 
+/// Run `cmds` with `{con_id}` replaced by the id of `root`, one after the
+/// other, stopping at the first that fails
+fn run_pre_commands(conn: &mut LiveProvider, root: &Node, cmds: &[String],
+    dry_run: bool) -> Result<(), AppError>
+{
+    for cmd in cmds {
+        let cmd = cmd.replace("{con_id}", &root.id.to_string());
+        if dry_run {
+            output::result(&cmd);
+            continue
+        }
 
-/// For a given node id, get its info using a new swayipc call
-/// Calling swayipc each time we do this makes sense at the moment because we 
-/// only use info about one node once before altering the state again.
-fn get_latest_info(conn: &mut Connection, node_id: i64) 
--> Result<Node, AppError> {
+        let res = conn.run_command(&cmd)
+            .map_err(|e| AppError::PreCommand(cmd.clone(), e.to_string()))?;
+        tracing::debug!("{cmd}: {res:?}");
+        if let Some(Err(e)) = res.into_iter().find(Result::is_err) {
+            return Err(AppError::PreCommand(cmd, e.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Print how balancing would resize the children of a container, as a table
+fn print_diff(diff: &SizeDiff) {
+    output::result(format_args!("container {} ({:?})", diff.con_id, diff.layout));
+    output::result(format_args!("{:>10} {:>8} {:>8} {:>8}", 
+        "id", "now", "balanced", "change"));
+
+    for (id, before, after) in &diff.sizes {
+        output::result(format_args!("{id:>10} {before:>8} {after:>8} {:>+8}",
+            after - before));
+    }
+}
+
+/// Print where the `total` time went, as a table on stderr. Threads of
+/// `--jobs` have connections of their own, which are not counted.
+fn print_profile(profile: &Profile, total: Duration) {
+    let ipc = profile.tree_time + profile.command_time;
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+    output::diagnostic(format_args!("{:<12} {:>6} {:>10}", "", "calls", "ms"));
+    output::diagnostic(format_args!("{:<12} {:>6} {:>10.2}", 
+        "get_tree", profile.tree_fetches, ms(profile.tree_time)));
+    output::diagnostic(format_args!("{:<12} {:>6} {:>10.2}",
+        "run_command", profile.commands, ms(profile.command_time)));
+    output::diagnostic(format_args!("{:<12} {:>6} {:>10.2}",
+        "computation", "", ms(total.saturating_sub(ipc))));
+}
+
+/// Focus the node with `id` again, unless it has gone away in the meantime
+fn restore_focus(conn: &mut LiveProvider, id: i64) -> Result<(), AppError> {
     let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
-    find_by_id(&tree, node_id).ok_or(AppError::NodeGone).cloned()
+    if find_by_id(&tree, id).is_none() { return Ok(()) }
+
+    conn.run_command(&format!("[con_id={id}] focus"))
+        .map_err(|_| AppError::Focus)?;
+    Ok(())
 }
 
-fn balance(conn: &mut Connection, root: &Node) -> Result<(), AppError> {
-    let mut q: VecDeque<i64> = VecDeque::from(vec![root.id]);
+/// Print `root` and everything below it, one node per line indented by depth
+fn print_tree(root: &Node) {
+    let mut stack = vec![(root, 0, false)];
 
-    while let Some(cur_id) = q.pop_front() {
-        let cur = get_latest_info(conn, cur_id)?;
-        if cur.nodes.is_empty() { continue }
+    while let Some((n, depth, floating)) = stack.pop() {
+        let r = &n.rect;
+        let label = n.app_id.as_deref().or(n.name.as_deref()).unwrap_or("");
+        output::result(format_args!("{:indent$}[{}] {:?} {},{} {}x{} {label}{}",
+            "", n.id, n.layout, r.x, r.y, r.width, r.height,
+            if floating { " (floating)" } else { "" }, indent = 2 * depth));
 
-        let (get_dim, dir): (fn(&Node) -> i32, &str)= match cur.layout {
-            NodeLayout::SplitH => (|n| n.rect.width, "right"),
-            NodeLayout::SplitV => (|n| n.rect.height, "down"),
-            _ => break,
-        };
+        // Pushed in reverse so they come off the stack in order
+        stack.extend(n.floating_nodes.iter().rev().map(|c| (c, depth + 1, true)));
+        stack.extend(n.nodes.iter().rev().map(|c| (c, depth + 1, false)));
+    }
+}
+
+/// How running went, as far as the exit code is concerned
+enum Outcome {
+    Changed,
+    /// Everything was balanced already, or there was nothing to balance
+    Unchanged,
+    /// Some container did not converge in time
+    Unconverged,
+    /// Checking found the layout too far off balance
+    Imbalanced,
+}
 
-        let sum_dim: i32 = cur.nodes.iter().map(get_dim).sum();
-        let desired_dim = sum_dim / cur.nodes.len() as i32;
-
-        // This should happen at most (\Sum_{k=1}^{num_of_children} k) times
-        let n = cur.nodes.len() as f64;
-        let max_iterations = (0.5 * n * (n + 1.0)).round() as usize;
-
-        for _ in 1..max_iterations {
-            // Loop until we were able to resize all children to the requested
-            // size. This may take multiple tries if there is not enough space
-            // in the adjacent container to grow into.
-            let mut succeeded = true;
-
-            // Once all except the last been resized, 
-            // the last one should already have the right size
-            let all_except_last = cur.nodes.iter()
-                .take(cur.nodes.len()-1)
-                .map(|Node {id,..}| id);
-
-            for child_id in all_except_last {
-                let child = get_latest_info(conn, *child_id).unwrap();
-                let diff = desired_dim - get_dim(&child);
-
-                let change = if diff < 0 { "shrink" } else { "grow" };
-                let diff = diff.abs();
-
-                let child_id = child.id;
-                let cmd = format!("[con_id={child_id}] resize {change} {dir} {diff} px");
-
-                // run_command returns a Result<Vec<Result<_,_>>,_>.
-                // The outermost result indicates whether executing the command 
-                // went wrong in some way. The innermost vector of results
-                // indicates, for each command, the result of executing the 
-                // command. The outermost Result may not go wrong here
-                let res = conn.run_command(cmd).map_err(|_| AppError::Resize)?;
-
-                // The innermost command can only be of the "cannot resize" type
-                // any other error is unexpected and should propegate
-                if let Err(e) = res.first().unwrap() {
-                    match e {
-                        CommandParse(e) => match e.as_str() {
-                            "Cannot resize any further" => succeeded = false,
-                            _ => return Err(AppError::Resize),
-                        },
-                        _ => return Err(AppError::Resize),
+impl Outcome {
+    fn of(report: &BalanceReport) -> Self {
+        if report.containers.iter().any(|c| c.timed_out) {
+            Outcome::Unconverged
+        } else if report.changed() {
+            Outcome::Changed
+        } else {
+            Outcome::Unchanged
+        }
+    }
+}
+
+
+fn main() -> ExitCode {
+    let arg_matches = cli::command().get_matches();
+
+    // Global flags are passed down to, but not always up from, subcommands
+    let innermost = arg_matches.subcommand().map_or(&arg_matches, |(_, m)| m);
+    output::init_logging(innermost.get_count("verbose"), innermost.get_flag("quiet"));
+    let exit_unchanged = innermost.get_flag("exit-unchanged");
+
+    // See `cli::EXIT_CODES`
+    match try_main(&arg_matches) {
+        Ok(Outcome::Unchanged) if exit_unchanged => ExitCode::from(2),
+        Ok(Outcome::Changed | Outcome::Unchanged) => ExitCode::SUCCESS,
+        Ok(Outcome::Unconverged) => ExitCode::from(3),
+        Ok(Outcome::Imbalanced) => ExitCode::from(4),
+        Err(e) => {
+            output::error(&e);
+            if let Some(source) = std::error::Error::source(&e) {
+                tracing::debug!("caused by: {source}");
+            }
+            match e {
+                AppError::Resize => ExitCode::from(3),
+                AppError::Partial(failed) => {
+                    for (id, e) in &failed {
+                        output::error(format_args!("[{id}] {e}"));
                     }
-                };
+                    let resize = |(_, e): &(i64, AppError)|
+                        matches!(e, AppError::Resize);
+                    match failed.iter().all(resize) {
+                        true => ExitCode::from(3),
+                        false => ExitCode::FAILURE,
+                    }
+                },
+                _ => ExitCode::FAILURE,
             }
-            if succeeded { break }
+        },
+    }
+}
+
+fn try_main(arg_matches: &ArgMatches) -> Result<Outcome, AppError> {
+    let socket = arg_matches.get_one::<PathBuf>("socket")
+        .map(PathBuf::as_path);
+    let config = config::load()?;
+
+    // Simulating needs no window manager, so it goes before connecting
+    let balance_m = match arg_matches.subcommand() {
+        Some(("balance", m)) => m,
+        _ => arg_matches,
+    };
+    if let Some(path) = balance_m.get_one::<PathBuf>("simulate-from-json") {
+        return simulate(path, balance_m, &cli::options(balance_m, &config));
+    }
+
+    let started = Instant::now();
+    let mut conn = LiveProvider::from(connect(socket)?);
+
+    let outcome = match arg_matches.subcommand() {
+        Some(("restore", m)) => {
+            let path = m.get_one::<PathBuf>("path").unwrap();
+            state::restore(&mut conn, &state::load(path)?)?;
+            Ok(Outcome::Changed)
+        },
+        Some(("watch", m)) => {
+            let opts = options(&mut conn, m, &config)?;
+            let run = |trigger: &str| {
+                run(&mut conn, socket, m, &opts, Some(trigger)).map(drop)
+            };
+            watch::watch(socket, &cli::timing(m), run)?;
+            Ok(Outcome::Changed)
+        },
+        Some(("check", m)) => check(&mut conn, m, &cli::weighing(m, &config)),
+        Some(("list", m)) => list(&mut conn, m, &cli::weighing(m, &config)),
+        Some(("tree", m)) => {
+            let (tree, workspaces) = fetch(&mut conn, m)?;
+            targets(&tree, &workspaces, m)?.into_iter().for_each(print_tree);
+            Ok(Outcome::Unchanged)
+        },
+        Some(("balance", m)) => {
+            let opts = options(&mut conn, m, &config)?;
+            run(&mut conn, socket, m, &opts, None)
+        },
+        _ => {
+            let opts = options(&mut conn, arg_matches, &config)?;
+            run(&mut conn, socket, arg_matches, &opts, None)
+        },
+    };
+
+    let innermost = arg_matches.subcommand().map_or(arg_matches, |(_, m)| m);
+    if innermost.get_flag("profile") {
+        print_profile(&conn.profile(), started.elapsed());
+    }
+    outcome
+}
+
+/// The options in `m` and `config`, with the scales of the outputs if they
+/// are in device pixels
+fn options(conn: &mut LiveProvider, m: &ArgMatches, config: &Config)
+-> Result<Options, AppError> {
+    let mut opts = cli::options(m, config);
+
+    if m.get_one::<String>("pixels").is_some_and(|p| p == "device") {
+        opts.device_scales = conn.get_outputs()
+            .map_err(|_| AppError::GetOutputs)?
+            .into_iter()
+            // Disabled outputs have a scale of -1
+            .filter_map(|o| o.scale.filter(|s| *s > 0.0).map(|s| (o.name, s)))
+            .collect();
+    }
+    Ok(opts)
+}
+
+/// Balance whatever the arguments ask for, as it is right now. When watching,
+/// `trigger` is the event that led to it.
+fn run(conn: &mut LiveProvider, socket: Option<&Path>, arg_matches: &ArgMatches,
+    opts: &Options, trigger: Option<&str>) -> Result<Outcome, AppError>
+{
+    let (tree, workspaces) = fetch(conn, arg_matches)?;
+    let to_balance = targets(&tree, &workspaces, arg_matches)?;
+
+    if arg_matches.get_flag("json") {
+        print_plan(conn, &to_balance, opts)?;
+        return Ok(Outcome::Unchanged);
+    }
+    if arg_matches.get_flag("show-diff") {
+        for root in &to_balance {
+            diff(&tree, root, opts)?.iter().for_each(print_diff);
+        }
+        return Ok(Outcome::Unchanged);
+    }
+    if arg_matches.get_flag("emit-script") {
+        for root in &to_balance {
+            script(&tree, root, opts)?.iter().for_each(output::result);
         }
-        q.extend(cur.nodes.iter().map(|n| n.id));
+        return Ok(Outcome::Unchanged);
     }
 
-    Ok(())
+    // Rebalancing every screen at once is too much to do on a mistyped key.
+    // Watching was asked for explicitly, and can not stop to ask each time.
+    let everything = arg_matches.get_flag("all") || arg_matches.get_flag("root");
+    if everything && !opts.dry_run && !arg_matches.get_flag("yes") 
+        && trigger.is_none() 
+    {
+        let workspaces = to_balance.iter()
+            .map(|root| workspace_nodes(root).len().max(1))
+            .sum();
+        let containers = to_balance.iter()
+            .map(|root| bfs_collect(root, |n| !n.nodes.is_empty()).len())
+            .sum();
+        let question = format_args!("Balance {workspaces} workspace(s) with \
+            {containers} container(s)?");
+        if !output::confirm(question) {
+            return Err(AppError::Unconfirmed(workspaces, containers));
+        }
+    }
+
+    if let Some(path) = arg_matches.get_one::<PathBuf>("save-state") {
+        let saved: Vec<_> = to_balance.iter()
+            .flat_map(|root| state::snapshot(root))
+            .collect();
+        state::save(path, &saved)?;
+    }
+
+    let pre: Vec<String> = arg_matches.get_many::<String>("pre-command")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    for root in &to_balance {
+        run_pre_commands(conn, root, &pre, opts.dry_run)?;
+    }
+
+    // Floating windows take no space from the tiled ones, so they are done
+    // first, whether or not there is anything tiled to balance
+    let started = Instant::now();
+    let mut floating = 0;
+    if arg_matches.get_flag("include-floating") {
+        for root in &to_balance {
+            floating += balance_floating(conn, root, opts)?;
+        }
+        tracing::info!("resized {floating} floating windows");
+    }
+
+    // Scripts reading the summary should get one either way
+    let json = arg_matches.get_one::<String>("format")
+        .is_some_and(|f| f == "json");
+    if to_balance.is_empty() {
+        tracing::warn!("nothing to balance");
+        if json { print_report(&BalanceReport::default(), Duration::ZERO) }
+        return Ok(Outcome::Unchanged);
+    }
+
+    if let Some(&aspect) = arg_matches.get_one::<f64>("aspect") {
+        let mut resized = 0;
+        for root in &to_balance {
+            resized += balance_aspect(conn, root, opts, aspect)?;
+        }
+        tracing::info!("resized {resized} windows towards the aspect ratio");
+        return match resized + floating {
+            0 => Ok(Outcome::Unchanged),
+            _ => Ok(Outcome::Changed),
+        };
+    }
+
+    let jobs = *arg_matches.get_one::<u16>("jobs").unwrap() as usize;
+    let many = arg_matches.get_flag("all") || arg_matches.contains_id("output")
+        || arg_matches.get_flag("focus-output");
+    let (mut report, failed) = match many {
+        true => balance_all(conn, socket, &to_balance, opts, jobs),
+        false => {
+            let report = balance_one(conn, socket, to_balance[0], opts, jobs)?;
+            tracing::info!("{report}");
+            (report, 0)
+        },
+    };
+    report.floating = floating;
+
+    if arg_matches.get_flag("restore-focus") && !opts.dry_run {
+        // Floating windows can hold the focus too, so look beyond `top_focus`
+        if let Some(focused) = tree.find_as_ref(|n| n.focused) {
+            restore_focus(conn, focused.id)?;
+        }
+    }
+
+    // Only the watch subcommand has the flag, and passes a trigger
+    let event = trigger.filter(|_| arg_matches.get_flag("events-json"));
+    if let Some(trigger) = event {
+        print_event(trigger, &to_balance, &report);
+    }
+
+    if json { print_report(&report, started.elapsed()) }
+
+    // Only bother the user when something actually moved
+    if arg_matches.get_flag("notify") && !opts.dry_run && report.changed() {
+        notify::send(&report.to_string());
+    }
+
+    let off = arg_matches.get_flag("strict") && !report.off_target().is_empty();
+    match failed {
+        0 if off => Ok(Outcome::Unconverged),
+        0 => Ok(Outcome::of(&report)),
+        n => Err(AppError::WorkspacesFailed(n)),
+    }
+}
+
+/// Balance the tree in the file at `path` in memory, and print the sizes it
+/// ends up with. Without a window manager to ask, the target is the whole
+/// tree with `--root`, otherwise the named or focused (or first) workspace.
+fn simulate(path: &Path, arg_matches: &ArgMatches, opts: &Options)
+-> Result<Outcome, AppError> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|_| AppError::Simulate(path.to_path_buf()))?;
+    let mut mock = MockProvider::from_json(&json)
+        .map_err(|_| AppError::Simulate(path.to_path_buf()))?;
+    let tree = mock.tree().clone();
+
+    let root = match arg_matches.get_one::<String>("workspace") {
+        _ if arg_matches.get_flag("root") => &tree,
+        Some(name) => workspace_nodes(&tree).into_iter()
+            .find(|w| w.name.as_deref() == Some(name.as_str()))
+            .ok_or_else(|| AppError::WorkspaceNotFound(name.to_string()))?,
+        // Trees saved by hand do not always have the focus set
+        None => focused_workspace_node(&tree)
+            .or_else(|| workspace_nodes(&tree).into_iter().next())
+            .ok_or(AppError::NoFocus)?,
+    };
+
+    let report = balance_with(&mut mock, root, opts)?;
+    tracing::info!("{report}");
+    if let Some(root) = find_by_id(mock.tree(), root.id) {
+        print_tree(root);
+    }
+    Ok(Outcome::of(&report))
+}
+
+/// How imbalanced every container in the targets is that balancing would
+/// resize, for the check and list subcommands
+fn imbalances(conn: &mut LiveProvider, arg_matches: &ArgMatches, 
+    opts: &Options) -> Result<Vec<Imbalance>, AppError> 
+{
+    let (tree, workspaces) = fetch(conn, arg_matches)?;
+    Ok(targets(&tree, &workspaces, arg_matches)?.into_iter()
+        .flat_map(|root| imbalance(root, opts))
+        .collect())
+}
+
+/// Report how imbalanced the targets are, for the check subcommand
+fn check(conn: &mut LiveProvider, arg_matches: &ArgMatches, opts: &Options)
+-> Result<Outcome, AppError> {
+    let found = imbalances(conn, arg_matches, opts)?;
+    let worst = found.iter().map(|i| i.percent).fold(0.0, f64::max);
+
+    match arg_matches.get_flag("json") {
+        true => output::json(&found),
+        false => output::result(format_args!("{worst:.1}%")),
+    }
+
+    let threshold = *arg_matches.get_one::<f64>("threshold").unwrap();
+    match worst > threshold {
+        true => Ok(Outcome::Imbalanced),
+        false => Ok(Outcome::Unchanged),
+    }
+}
+
+/// List the containers in the targets balancing would resize, for the list
+/// subcommand
+fn list(conn: &mut LiveProvider, arg_matches: &ArgMatches, opts: &Options)
+-> Result<Outcome, AppError> {
+    let found = imbalances(conn, arg_matches, opts)?;
+
+    if arg_matches.get_flag("json") {
+        output::json(&found);
+        return Ok(Outcome::Unchanged);
+    }
+
+    output::result(format_args!("{:>10} {:>8} {:<8} {:>9}", 
+        "con_id", "children", "layout", "imbalance"));
+    for i in &found {
+        output::result(format_args!("{:>10} {:>8} {:<8} {:>8.1}%", 
+            i.con_id, i.children, format!("{:?}", i.layout), i.percent));
+    }
+    Ok(Outcome::Unchanged)
 }
 
+/// The tree and the workspaces, agreeing on the workspace the arguments ask
+/// for (or the focused one). Both can not be fetched at once, so if the tree
+/// changed in between they are fetched once more.
+fn fetch(conn: &mut LiveProvider, arg_matches: &ArgMatches)
+-> Result<(Node, Vec<Workspace>), AppError> {
+    for _ in 0..2 {
+        let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
+        let workspaces = conn.get_workspaces()
+            .map_err(|_| AppError::GetWorkspaces)?;
 
-fn main() -> Result<(),AppError> {
-    let arg_matches = Command::new("sway-balance")
-        .author("Rintse")
-        .about("Balance a sway workspace, or some focus therein")
-        .arg(Arg::new("focus")
-            .long("focus")
-            .short('f')
-            .help("Balance the focus, instead of the entire container")
-            .action(clap::ArgAction::SetTrue))
-        .get_matches();
+        let workspace = match arg_matches.get_one::<String>("workspace") {
+            Some(name) => Some(find_workspace(&workspaces, name)?),
+            None => workspaces.iter().find(|w| w.focused),
+        };
+        let agree = workspace.is_none_or(|w| find_by_id(&tree, w.id).is_some());
+        if agree { return Ok((tree, workspaces)) }
+    }
+    Err(AppError::TreeMismatch)
+}
+
+/// The nodes the arguments ask to balance
+fn targets<'a>(tree: &'a Node, workspaces: &[Workspace],
+    arg_matches: &ArgMatches) -> Result<Vec<&'a Node>, AppError>
+{
+    if arg_matches.get_flag("root") {
+        return Ok(vec![tree]);
+    }
+    if arg_matches.get_flag("all") {
+        return Ok(workspace_nodes(tree));
+    }
+    if let Some(name) = arg_matches.get_one::<String>("output") {
+        return Ok(workspace_nodes(find_output(tree, name)?));
+    }
+    if let Some(mark) = arg_matches.get_one::<String>("mark") {
+        return Ok(vec![find_marked(tree, mark)?]);
+    }
+    if let Some(&id) = arg_matches.get_one::<i64>("con-id") {
+        return Ok(vec![find_by_id(tree, id).ok_or(AppError::NodeGone)?]);
+    }
+    if arg_matches.get_flag("focus-output") {
+        let workspace = focused_workspace_node(tree).ok_or(AppError::NoFocus)?;
+        let output = parent_of(tree, workspace.id).ok_or(AppError::NoFocus)?;
+        return Ok(match arg_matches.get_flag("include-hidden") {
+            true => workspace_nodes(output),
+            // An output shows the workspace it last focused
+            false => output.focus.first()
+                .and_then(|id| find_by_id(output, *id))
+                .into_iter()
+                .collect(),
+        });
+    }
+
+    let workspace_node = match arg_matches.get_one::<String>("workspace") {
+        Some(name) => find_by_id(tree, find_workspace(workspaces, name)?.id),
+        None => focused_workspace(tree, workspaces),
+    }.ok_or(AppError::NoFocus)?;
 
-    let mut conn = swayipc::Connection::new()
-        .map_err(|_| AppError::Conn)?;
+    // Without windows there is no focus to look for either, and balancing
+    // the workspace finds nothing to do
+    if workspace_node.nodes.is_empty() {
+        return Ok(vec![workspace_node]);
+    }
 
-    let tree = conn.get_tree()
-        .map_err(|_| AppError::GetTree)?;
-    let workspaces = conn.get_workspaces()
-        .map_err(|_| AppError::GetWorkspaces)?;
+    if arg_matches.get_flag("focus-child") 
+        || arg_matches.get_flag("focus-neighbors") 
+    {
+        let leaf = bfs_find(workspace_node, |n| n.focused)
+            .ok_or(AppError::NoFocus)?;
+        let parent = parent_of(workspace_node, leaf.id)
+            .ok_or(AppError::NoFocus)?;
+        return Ok(vec![parent]);
+    }
 
-    let focused_workspace = workspaces.iter()
-        .find(|w| w.focused)
-        .ok_or(AppError::NoFocus)?;
-    let focused_workspace_node = find_by_id(&tree, focused_workspace.id)
-        .ok_or(AppError::NoFocus)?;
+    if let Some(level) = arg_matches.get_one::<usize>("level") {
+        let focus = top_focus(workspace_node).ok_or(AppError::NoFocus)?;
+        let path = path_to(workspace_node, focus.id);
+        // Climbing past the workspace keeps balancing the workspace
+        let i = path.len().saturating_sub(1 + level);
+        return Ok(vec![path[i]]);
+    }
 
     let to_balance = match arg_matches.get_flag("focus") {
-        true => top_focus(focused_workspace_node).ok_or(AppError::NoFocus)?,
-        false => focused_workspace_node,
+        true => top_focus(workspace_node).ok_or(AppError::NoFocus)?,
+        false => workspace_node,
     };
     
-    balance(&mut conn, to_balance)
+    Ok(vec![to_balance])
 }
-
@@ -0,0 +1,32 @@
+//! Desktop notifications about what balancing did
+
+use std::env;
+use std::process::{Command, Stdio};
+
+
+/// Show `body` as a desktop notification, with a call to `Notify` of the
+/// `org.freedesktop.Notifications` service on the session bus. The
+/// notifications are an extra, so without a session bus (or gdbus to call it
+/// with) this does nothing.
+pub fn send(body: &str) {
+    if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() { return }
+
+    // The arguments are in the GVariant text format, typed so that gdbus need
+    // not ask the service for them: the app name, the id of the notification
+    // to replace, the icon, summary, body, actions, hints, and the timeout
+    let _ = Command::new("gdbus")
+        .args(["call", "--session",
+            "--dest", "org.freedesktop.Notifications",
+            "--object-path", "/org/freedesktop/Notifications",
+            "--method", "org.freedesktop.Notifications.Notify", "--",
+            "'sway-balance'", "uint32 0", "''", "'sway-balance'", &quoted(body),
+            "@as []", "@a{sv} {}", "int32 -1"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// `text` as a GVariant string
+fn quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
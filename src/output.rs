@@ -0,0 +1,77 @@
+//! Everything the tool prints goes through here. Results that were asked for
+//! go to stdout, logs and errors to stderr, so stdout can be piped into other
+//! tools.
+
+use std::fmt::Display;
+use std::io::{BufRead, IsTerminal, Write};
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+
+
+/// Log to stderr at the level the verbose flags ask for: warnings by default,
+/// and info, debug or trace for every repetition. `RUST_LOG` takes precedence
+/// over the flags, if set.
+pub fn init_logging(verbosity: u8, quiet: bool) {
+    let level = match (quiet, verbosity) {
+        (true, _) => Level::ERROR,
+        (_, 0) => Level::WARN,
+        (_, 1) => Level::INFO,
+        (_, 2) => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+/// A result that was asked for, like planned resize commands or json
+pub fn result(msg: impl Display) {
+    println!("{msg}");
+}
+
+/// A result that was asked for as json
+pub fn json(value: &impl serde::Serialize) {
+    result(to_json(value));
+}
+
+/// `value` as json. What this tool serializes are plain structs of numbers 
+/// and strings, which can not fail.
+pub fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// A line in a stream of results, flushed right away so whatever reads it
+/// does not wait for a buffer to fill
+pub fn event(msg: impl Display) {
+    let mut stdout = std::io::stdout().lock();
+    // Nothing to be done if whatever read it went away
+    let _ = writeln!(stdout, "{msg}").and_then(|_| stdout.flush());
+}
+
+/// Details about the run that were asked for, like timings, which are not
+/// results to pipe anywhere
+pub fn diagnostic(msg: impl Display) {
+    eprintln!("{msg}");
+}
+
+/// Something went wrong. Always printed, however quiet we are asked to be.
+pub fn error(msg: impl Display) {
+    eprintln!("error: {msg}");
+}
+
+/// Ask `question` on stderr and wait for a yes, if there is a terminal to
+/// answer on. Without one nobody can say yes.
+pub fn confirm(question: impl Display) -> bool {
+    if !std::io::stdin().is_terminal() { return false }
+
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() { return false }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
@@ -0,0 +1,364 @@
+//! The calls to the window manager balancing needs, behind a trait so they
+//! can be cached, or served without a window manager at all
+
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use swayipc::{Connection, Fallible, Node, NodeLayout};
+use swayipc::Error::CommandParse;
+use crate::{find_by_id, AppError};
+
+
+/// Where balancing gets its trees from, and sends its commands to
+pub trait TreeProvider {
+    /// The entire layout tree, as it is right now
+    fn get_tree(&mut self) -> Fallible<Node>;
+
+    /// Run `cmds`, separated by `;`. Like `Connection::run_command`, the
+    /// outer result is whether that went through, the inner ones whether each
+    /// of the commands succeeded.
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>>;
+
+    /// The node with `id`, as it is right now
+    fn get_node(&mut self, id: i64) -> Result<Node, AppError> {
+        let tree = self.get_tree().map_err(|_| AppError::GetTree)?;
+        find_by_id(&tree, id).ok_or(AppError::NodeGone).cloned()
+    }
+
+    /// Forget whatever was fetched before, so the next request asks the
+    /// window manager again
+    fn invalidate(&mut self) {}
+}
+
+impl<P: TreeProvider + ?Sized> TreeProvider for &mut P {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        (**self).get_tree()
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        (**self).run_command(cmds)
+    }
+
+    fn get_node(&mut self, id: i64) -> Result<Node, AppError> {
+        (**self).get_node(id)
+    }
+
+    fn invalidate(&mut self) {
+        (**self).invalidate()
+    }
+}
+
+
+/// A live connection to the window manager. Everything else a `Connection`
+/// can do is still available through it.
+pub struct LiveProvider {
+    conn: Connection,
+    profile: Profile,
+}
+
+/// How many trees were fetched and commands run through a `LiveProvider`,
+/// and how long the window manager took to answer them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Profile {
+    pub tree_fetches: usize,
+    pub tree_time: Duration,
+    pub commands: usize,
+    pub command_time: Duration,
+}
+
+impl LiveProvider {
+    /// The calls made so far
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+}
+
+impl From<Connection> for LiveProvider {
+    fn from(conn: Connection) -> Self {
+        LiveProvider { conn, profile: Profile::default() }
+    }
+}
+
+impl Deref for LiveProvider {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl DerefMut for LiveProvider {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl TreeProvider for LiveProvider {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        let started = Instant::now();
+        let tree = self.conn.get_tree();
+        self.profile.tree_fetches += 1;
+        self.profile.tree_time += started.elapsed();
+        tree
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        let started = Instant::now();
+        let res = self.conn.run_command(cmds);
+        self.profile.commands += 1;
+        self.profile.command_time += started.elapsed();
+        res
+    }
+}
+
+
+/// Fetches the tree once, and keeps serving it until a command may have
+/// changed it. Balancing a container that is already balanced then costs no
+/// ipc at all.
+pub struct CachingProvider<P> {
+    inner: P,
+    tree: Option<Node>,
+}
+
+impl<P: TreeProvider> CachingProvider<P> {
+    pub fn new(inner: P) -> Self {
+        CachingProvider { inner, tree: None }
+    }
+
+    fn cached(&mut self) -> Fallible<&Node> {
+        if self.tree.is_none() {
+            self.tree = Some(self.inner.get_tree()?);
+        }
+        Ok(self.tree.as_ref().unwrap())
+    }
+}
+
+impl<P: TreeProvider> TreeProvider for CachingProvider<P> {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        self.cached().cloned()
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        self.invalidate();
+        self.inner.run_command(cmds)
+    }
+
+    fn get_node(&mut self, id: i64) -> Result<Node, AppError> {
+        let tree = self.cached().map_err(|_| AppError::GetTree)?;
+        find_by_id(tree, id).ok_or(AppError::NodeGone).cloned()
+    }
+
+    /// Forget the cached tree, so the next request fetches a fresh one
+    fn invalidate(&mut self) {
+        self.tree = None;
+        self.inner.invalidate();
+    }
+}
+
+
+/// A window manager that only exists in memory. It serves a tree, and applies
+/// the `grow`/`shrink` and `set` resizes it is sent to that tree the way sway
+/// would: a child grows at the expense of its next sibling (or its previous
+/// one, for `left` and `up`), and the children of both are laid out again. 
+/// Every command is recorded, whether it is understood or not.
+pub struct MockProvider {
+    tree: Node,
+    /// Trees to switch to on the following calls to `get_tree`, to script
+    /// changes that did not come from our own commands
+    script: VecDeque<Node>,
+    /// Every command run, in order
+    pub commands: Vec<String>,
+}
+
+impl MockProvider {
+    pub fn new(tree: Node) -> Self {
+        MockProvider { tree, script: VecDeque::new(), commands: Vec::new() }
+    }
+
+    /// A mock serving the tree in `json`, as printed by `swaymsg -t get_tree`
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json).map(MockProvider::new)
+    }
+
+    /// Serve `tree` from the next call to `get_tree` on
+    pub fn push_state(&mut self, tree: Node) {
+        self.script.push_back(tree);
+    }
+
+    /// The tree as it is now
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    /// Apply a single command to the tree
+    fn apply(&mut self, cmd: &str) -> Fallible<()> {
+        let unsupported = || CommandParse(format!("Unsupported command: {cmd}"));
+
+        let (id, rest) = cmd.strip_prefix("[con_id=")
+            .and_then(|c| c.split_once(']'))
+            .ok_or_else(unsupported)?;
+        let id: i64 = id.parse().map_err(|_| unsupported())?;
+        let words: Vec<&str> = rest.split_whitespace().collect();
+
+        let (sign, dir, px) = match words[..] {
+            ["resize", "grow", dir, px, "px", ..] => (1, dir, px),
+            ["resize", "shrink", dir, px, "px", ..] => (-1, dir, px),
+            ["resize", "set", axis, px, "px", ..] => {
+                let px: i32 = px.parse().map_err(|_| unsupported())?;
+                return self.set(id, axis, px);
+            },
+            ["resize", "set", axis, ppt, "ppt", ..] => {
+                let ppt: i32 = ppt.parse().map_err(|_| unsupported())?;
+                let parent = crate::parent_of(&self.tree, id)
+                    .ok_or_else(unsupported)?;
+                let parent_dim = match axis {
+                    "width" => parent.rect.width,
+                    _ => parent.rect.height,
+                };
+                return self.set(id, axis, ppt * parent_dim / 100);
+            },
+            // Anything else does not change sizes in a way we model
+            _ => return Ok(()),
+        };
+        let px: i32 = px.parse().map_err(|_| unsupported())?;
+        let towards_next = matches!(dir, "right" | "down");
+
+        resize(&mut self.tree, id, sign * px, towards_next)
+    }
+
+    /// Set the size along `axis` of the node with `id` to `px`, taking the
+    /// space from its next sibling, or its previous one if it is the last
+    fn set(&mut self, id: i64, axis: &str, px: i32) -> Fallible<()> {
+        let no_further = || CommandParse("Cannot resize any further".to_string());
+        let parent = crate::parent_of(&self.tree, id).ok_or_else(no_further)?;
+        let i = parent.nodes.iter().position(|n| n.id == id).unwrap();
+        let rect = parent.nodes[i].rect;
+
+        let delta = match axis {
+            "width" => px - rect.width,
+            "height" => px - rect.height,
+            _ => return Ok(()),
+        };
+        let towards_next = i + 1 < parent.nodes.len();
+        resize(&mut self.tree, id, delta, towards_next)
+    }
+}
+
+impl TreeProvider for MockProvider {
+    fn get_tree(&mut self) -> Fallible<Node> {
+        if let Some(next) = self.script.pop_front() {
+            self.tree = next;
+        }
+        Ok(self.tree.clone())
+    }
+
+    fn run_command(&mut self, cmds: &str) -> Fallible<Vec<Fallible<()>>> {
+        Ok(cmds.split(';')
+            .map(str::trim)
+            .map(|cmd| {
+                self.commands.push(cmd.to_string());
+                self.apply(cmd)
+            })
+            .collect())
+    }
+}
+
+
+/// The node with `id`, mutably
+fn find_mut(root: &mut Node, id: i64) -> Option<&mut Node> {
+    if root.id == id { return Some(root) }
+    root.nodes.iter_mut().find_map(|n| find_mut(n, id))
+}
+
+/// Grow the node with `id` by `delta` along the axis of its parent, taking
+/// the space from its next sibling, or its previous one if not `towards_next`
+fn resize(root: &mut Node, id: i64, delta: i32, towards_next: bool)
+-> Fallible<()> {
+    let no_further = || CommandParse("Cannot resize any further".to_string());
+
+    let parent_id = crate::parent_of(root, id).ok_or_else(no_further)?.id;
+    let parent = find_mut(root, parent_id).unwrap();
+    let i = parent.nodes.iter().position(|n| n.id == id).unwrap();
+
+    let horizontal = match parent.layout {
+        NodeLayout::SplitH => true,
+        NodeLayout::SplitV => false,
+        _ => return Err(no_further()),
+    };
+    // The pair of siblings in order, the first giving its far edge to the
+    // second or the other way around
+    let (a, b, delta) = match towards_next {
+        true if i + 1 < parent.nodes.len() => (i, i + 1, delta),
+        false if i > 0 => (i - 1, i, -delta),
+        _ => return Err(no_further()),
+    };
+    let (first, rest) = parent.nodes.split_at_mut(b);
+    let (node, next) = (&mut first[a], &mut rest[0]);
+
+    let (dim, next_dim) = match horizontal {
+        true => (node.rect.width, next.rect.width),
+        false => (node.rect.height, next.rect.height),
+    };
+    if dim + delta < 1 || next_dim - delta < 1 { return Err(no_further()) }
+
+    match horizontal {
+        true => {
+            node.rect.width += delta;
+            next.rect.width -= delta;
+            next.rect.x += delta;
+        },
+        false => {
+            node.rect.height += delta;
+            next.rect.height -= delta;
+            next.rect.y += delta;
+        },
+    }
+    layout(node);
+    layout(next);
+    Ok(())
+}
+
+/// Fit the children of `node` into its rect again, keeping their proportions
+fn layout(node: &mut Node) {
+    let rect = node.rect;
+    let n = node.nodes.len() as i32;
+    if n == 0 { return }
+
+    match node.layout {
+        NodeLayout::SplitH => {
+            let old: i32 = node.nodes.iter().map(|c| c.rect.width).sum();
+            let mut x = rect.x;
+            for (i, c) in node.nodes.iter_mut().enumerate() {
+                // The last child takes whatever rounding left over
+                let width = match i as i32 == n - 1 {
+                    true => rect.x + rect.width - x,
+                    false => c.rect.width * rect.width / old.max(1),
+                };
+                c.rect.x = x;
+                c.rect.y = rect.y;
+                c.rect.width = width;
+                c.rect.height = rect.height;
+                x += width;
+            }
+        },
+        NodeLayout::SplitV => {
+            let old: i32 = node.nodes.iter().map(|c| c.rect.height).sum();
+            let mut y = rect.y;
+            for (i, c) in node.nodes.iter_mut().enumerate() {
+                let height = match i as i32 == n - 1 {
+                    true => rect.y + rect.height - y,
+                    false => c.rect.height * rect.height / old.max(1),
+                };
+                c.rect.x = rect.x;
+                c.rect.y = y;
+                c.rect.width = rect.width;
+                c.rect.height = height;
+                y += height;
+            }
+        },
+        // Tabs and stacks show all their children in the full rect
+        _ => node.nodes.iter_mut().for_each(|c| c.rect = rect),
+    }
+
+    node.nodes.iter_mut().for_each(layout);
+}
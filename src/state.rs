@@ -0,0 +1,62 @@
+//! Saving the sizes of windows before balancing, so they can be restored
+
+use std::fs;
+use std::path::Path;
+use swayipc::Node;
+use crate::{bfs_collect, find_by_id, output, resize_node, AppError};
+use crate::provider::TreeProvider;
+
+
+/// The size a node had when it was saved
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SavedNode {
+    pub con_id: i64,
+    pub width: i32,
+    pub height: i32,
+}
+
+
+/// The sizes of everything below `root` that balancing it may resize, 
+/// parents before their children
+pub fn snapshot(root: &Node) -> Vec<SavedNode> {
+    bfs_collect(root, |n| n.id != root.id).into_iter()
+        .map(|n| SavedNode {
+            con_id: n.id,
+            width: n.rect.width,
+            height: n.rect.height,
+        })
+        .collect()
+}
+
+pub fn save(path: &Path, saved: &[SavedNode]) -> Result<(), AppError> {
+    fs::write(path, output::to_json(&saved)).map_err(|_| AppError::SaveState(path.to_path_buf()))
+}
+
+pub fn load(path: &Path) -> Result<Vec<SavedNode>, AppError> {
+    let err = || AppError::LoadState(path.to_path_buf());
+    let json = fs::read_to_string(path).map_err(|_| err())?;
+    serde_json::from_str(&json).map_err(|_| err())
+}
+
+/// Resize the saved nodes back to their saved sizes. Nodes that no longer
+/// exist are skipped.
+pub fn restore(conn: &mut impl TreeProvider, saved: &[SavedNode]) 
+-> Result<(), AppError> {
+    let tree = conn.get_tree().map_err(|_| AppError::GetTree)?;
+
+    for SavedNode { con_id, width, height } in saved {
+        if find_by_id(&tree, *con_id).is_none() {
+            tracing::warn!("container {con_id} is gone, skipping it");
+            continue
+        }
+
+        let res = resize_node(conn, *con_id, Some(*width), Some(*height))?;
+
+        // Sizes that can not be restored exactly are not worth failing over
+        if let Err(e) = res {
+            tracing::warn!("could not restore container {con_id}: {e}");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,827 @@
+//! Balancing trees that only exist in a `MockProvider`
+
+use serde_json::json;
+use super::*;
+use crate::provider::MockProvider;
+
+
+/// A node of `node_type`, with nothing set but what balancing looks at
+fn node(id: i64, node_type: &str, layout: &str, rect: (i32, i32, i32, i32),
+    nodes: Vec<Node>) -> Node
+{
+    let (x, y, width, height) = rect;
+    let none = json!({ "x": 0, "y": 0, "width": 0, "height": 0 });
+    serde_json::from_value(json!({
+        "id": id, "name": null, "type": node_type, "border": "none",
+        "current_border_width": 0, "layout": layout, "percent": null,
+        "rect": { "x": x, "y": y, "width": width, "height": height },
+        "window_rect": none, "deco_rect": none, "geometry": none,
+        "urgent": false, "focused": false, "focus": [], "nodes": nodes,
+        "floating_nodes": [], "sticky": false,
+    })).unwrap()
+}
+
+/// A window at `rect`
+fn leaf(id: i64, rect: (i32, i32, i32, i32)) -> Node {
+    node(id, "con", "none", rect, vec![])
+}
+
+/// `rect` cut into pieces of `sizes` along the axis of `layout`
+fn rects(layout: &str, rect: (i32, i32, i32, i32), sizes: &[i32])
+-> Vec<(i32, i32, i32, i32)> {
+    let (x, y, width, height) = rect;
+    let mut pos = 0;
+    sizes.iter()
+        .map(|s| {
+            pos += s;
+            match layout {
+                "splith" => (x + pos - s, y, *s, height),
+                _ => (x, y + pos - s, width, *s),
+            }
+        })
+        .collect()
+}
+
+/// A split container at `rect` holding windows of `sizes` along its axis,
+/// numbered on from `id`
+fn split(id: i64, layout: &str, rect: (i32, i32, i32, i32), sizes: &[i32])
+-> Node {
+    let windows = rects(layout, rect, sizes).into_iter().enumerate()
+        .map(|(i, r)| leaf(id + 1 + i as i64, r))
+        .collect();
+    node(id, "con", layout, rect, windows)
+}
+
+/// `split` as a workspace
+fn workspace(id: i64, layout: &str, rect: (i32, i32, i32, i32), sizes: &[i32])
+-> Node {
+    let mut ws = split(id, layout, rect, sizes);
+    ws.node_type = NodeType::Workspace;
+    ws.name = Some("1".to_string());
+    ws
+}
+
+/// `tree` balanced from its root in a mock, and how that went
+fn balanced(tree: Node, opts: &Options)
+-> (MockProvider, Result<BalanceReport, AppError>) {
+    let mut mock = MockProvider::new(tree.clone());
+    let res = balance_with(&mut mock, &tree, opts);
+    (mock, res)
+}
+
+/// The sizes of the children of the container with `id` along its axis
+fn sizes(tree: &Node, id: i64) -> Vec<i32> {
+    let n = find_by_id(tree, id).unwrap();
+    let axis = Axis::of(n.layout).unwrap();
+    n.nodes.iter().map(|c| axis.dim(c)).collect()
+}
+
+
+#[test]
+fn walks_go_breadth_first() {
+    let ws = node(1, "workspace", "splith", (0, 0, 1000, 800), vec![
+        split(2, "splitv", (0, 0, 500, 800), &[400, 400]),
+        leaf(5, (500, 0, 500, 800)),
+    ]);
+    let mut visited = Vec::new();
+    bfs_visit(&ws, |n, depth| visited.push((n.id, depth)));
+    assert_eq!(visited, [(1, 0), (2, 1), (5, 1), (3, 2), (4, 2)]);
+
+    let windows: Vec<i64> = bfs_collect(&ws, |n| n.nodes.is_empty()).iter()
+        .map(|n| n.id)
+        .collect();
+    assert_eq!(windows, [5, 3, 4]);
+    // The shallowest match, rather than the first depth first
+    assert_eq!(bfs_find(&ws, |n| n.nodes.is_empty()).map(|n| n.id), Some(5));
+    assert!(bfs_find(&ws, |n| n.id == 9).is_none());
+}
+
+#[test]
+fn balanced_container_sends_nothing() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[500, 500]);
+    let (mock, res) = balanced(ws, &Options::default());
+
+    assert!(!res.unwrap().changed());
+    assert!(mock.commands.is_empty());
+}
+
+#[test]
+fn uneven_split_converges() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[300, 700]);
+    let (mock, res) = balanced(ws, &Options::default());
+
+    assert_eq!(res.unwrap().px_moved(), 200);
+    assert_eq!(mock.commands, ["[con_id=2] resize grow right 200 px"]);
+    assert_eq!(sizes(mock.tree(), 1), [500, 500]);
+}
+
+#[test]
+fn tabbed_container_is_descended_into() {
+    let rect = (0, 0, 1000, 800);
+    let tabs = node(1, "workspace", "tabbed", rect, vec![
+        leaf(2, rect),
+        split(3, "splitv", rect, &[200, 600]),
+    ]);
+    let (mock, res) = balanced(tabs, &Options::default());
+
+    // Only the split in the second tab has anything to divide
+    assert_eq!(res.unwrap().containers.len(), 1);
+    assert_eq!(mock.commands, ["[con_id=4] resize grow down 200 px"]);
+    assert_eq!(sizes(mock.tree(), 3), [400, 400]);
+}
+
+#[test]
+fn vanishing_child_fails_its_container() {
+    let rect = (0, 0, 1000, 800);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 800), &[200, 600]),
+        leaf(5, (300, 0, 700, 800)),
+    ]);
+    let mut mock = MockProvider::new(ws.clone());
+    mock.push_state(ws.clone());
+    // Closed after the workspace is balanced, before its first child is
+    let mut closed = ws.clone();
+    closed.nodes.remove(0);
+    mock.push_state(closed);
+
+    let res = balance_with(&mut mock, &ws, &Options::default());
+    match res {
+        Err(AppError::Partial(failed)) => {
+            assert!(matches!(failed[..], [(2, AppError::NodeGone)]));
+        },
+        res => panic!("expected container 2 to be gone, got {res:?}"),
+    }
+}
+
+#[test]
+fn empty_workspace_has_nothing_to_balance() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[]);
+    let (mock, res) = balanced(ws, &Options::default());
+
+    assert!(res.unwrap().containers.is_empty());
+    assert!(mock.commands.is_empty());
+}
+
+/// Pseudo random numbers, the same on every run
+struct Lcg(u64);
+
+impl Lcg {
+    /// A number in `0..n`
+    fn below(&mut self, n: i32) -> i32 {
+        self.0 = self.0.wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as i32
+    }
+}
+
+/// A random tree of splits filling `rect`, at most `depth` deep, its nodes
+/// numbered on from `next_id`
+fn random_tree(rng: &mut Lcg, next_id: &mut i64, rect: (i32, i32, i32, i32),
+    depth: usize) -> Node
+{
+    *next_id += 1;
+    let id = *next_id;
+    if depth == 0 || rng.below(10) < 4 || rect.2.min(rect.3) < 130 {
+        return leaf(id, rect);
+    }
+
+    let layout = ["splith", "splitv"][rng.below(2) as usize];
+    let dim = if layout == "splith" { rect.2 } else { rect.3 };
+    let n = 2 + rng.below((dim / 60).clamp(2, 6) - 1) as usize;
+    let mut cuts = std::collections::BTreeSet::from([0, dim]);
+    while cuts.len() < n + 1 { cuts.insert(1 + rng.below(dim - 1)); }
+    let cuts: Vec<i32> = cuts.into_iter().collect();
+    let sizes: Vec<i32> = cuts.windows(2).map(|w| w[1] - w[0]).collect();
+
+    let children = rects(layout, rect, &sizes).into_iter()
+        .map(|r| random_tree(rng, next_id, r, depth - 1))
+        .collect();
+    node(id, "con", layout, rect, children)
+}
+
+/// How far apart the largest and smallest child of every split container
+/// from `n` down are, by id. Shrinking a container a lot can lay out its
+/// smallest children without any size, and those are left alone along with
+/// everything below them.
+fn spreads(n: &Node, found: &mut HashMap<i64, i32>) {
+    if n.nodes.is_empty() { return }
+    let sizes = sizes(n, n.id);
+    if sizes.contains(&0) { return }
+
+    let spread = sizes.iter().max().unwrap() - sizes.iter().min().unwrap();
+    found.insert(n.id, spread);
+    n.nodes.iter().for_each(|c| spreads(c, found));
+}
+
+#[test]
+fn random_trees_end_balanced() {
+    let mut rng = Lcg(89);
+
+    for _ in 0..300 {
+        let rect = (0, 0, 800 + rng.below(3040), 600 + rng.below(1560));
+        let mut tree = random_tree(&mut rng, &mut 0, rect, 3);
+        if tree.nodes.is_empty() { continue }
+        tree.node_type = NodeType::Workspace;
+
+        let mut before = HashMap::new();
+        spreads(&tree, &mut before);
+        let (mock, res) = balanced(tree, &Options::default());
+        res.unwrap();
+
+        let mut after = HashMap::new();
+        spreads(mock.tree(), &mut after);
+        for (id, after) in after {
+            assert!(after <= before[&id].max(1),
+                "container {id} went from {}px apart to {after}px", before[&id]);
+            assert!(after <= 1, "container {id} ended {after}px apart");
+        }
+    }
+}
+
+#[test]
+fn balancing_again_sends_nothing() {
+    // A third of 900px is 33% of it, which is 297px rather than 300px
+    let rect = (0, 0, 900, 900);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        leaf(2, (0, 0, 100, 900)),
+        split(3, "splitv", (100, 0, 300, 900), &[100, 500, 300]),
+        leaf(7, (400, 0, 500, 900)),
+    ]);
+    let modes = [
+        Options::default(),
+        Options { absolute: true, ..Default::default() },
+        Options { ppt: true, ..Default::default() },
+    ];
+
+    for opts in modes {
+        let (mock, res) = balanced(ws.clone(), &opts);
+        assert!(res.unwrap().changed());
+        let (again, res) = balanced(mock.tree().clone(), &opts);
+        res.unwrap();
+        assert!(again.commands.is_empty(), "{opts:?} sent {:?}", again.commands);
+    }
+}
+
+/// A mock shared by every thread balancing in parallel
+struct Shared<'a>(&'a std::sync::Mutex<MockProvider>);
+
+impl TreeProvider for Shared<'_> {
+    fn get_tree(&mut self) -> swayipc::Fallible<Node> {
+        self.0.lock().unwrap().get_tree()
+    }
+
+    fn run_command(&mut self, cmds: &str)
+    -> swayipc::Fallible<Vec<swayipc::Fallible<()>>> {
+        self.0.lock().unwrap().run_command(cmds)
+    }
+}
+
+#[test]
+fn parallel_balancing_keeps_the_depth() {
+    let rect = (0, 0, 1000, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 1000), &[300, 700]),
+        split(5, "splitv", (300, 0, 700, 1000), &[300, 700]),
+    ]);
+    let ratios = [
+        Options { master: Some(60), ..Default::default() },
+        Options { ratio: Ratio::Fib, ..Default::default() },
+    ];
+
+    for opts in ratios {
+        let (serial, res) = balanced(ws.clone(), &opts);
+        res.unwrap();
+
+        let mock = std::sync::Mutex::new(MockProvider::new(ws.clone()));
+        let connect = || Ok::<_, AppError>(Shared(&mock));
+        balance_parallel(connect, &ws, &opts, 2).unwrap();
+
+        let parallel = mock.into_inner().unwrap();
+        for id in [1, 2, 5] {
+            assert_eq!(sizes(parallel.tree(), id), sizes(serial.tree(), id),
+                "container {id} with {opts:?}");
+        }
+    }
+}
+
+#[test]
+fn command_budget_lasts_all_passes() {
+    let ws = workspace(1, "splith", (0, 0, 900, 800), &[100, 200, 600]);
+    let opts = Options { max_commands: Some(1), passes: 3, ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+
+    assert_eq!(mock.commands.len(), 1);
+    assert_eq!(res.unwrap().deferred(), 1);
+}
+
+#[test]
+fn command_budget_lasts_all_workspaces() {
+    let rect = (0, 0, 900, 800);
+    let root = node(0, "root", "splith", rect, vec![
+        workspace(1, "splith", rect, &[300, 600]),
+        workspace(4, "splith", rect, &[300, 600]),
+    ]);
+    let mut mock = MockProvider::new(root.clone());
+    let opts = Options { max_commands: Some(1), ..Default::default() };
+    let (report, failed) = balance_each(&workspace_nodes(&root), &opts,
+        |ws, opts| balance_with(&mut mock, ws, opts));
+
+    assert_eq!((report.commands(), failed), (1, 0));
+    assert_eq!(mock.commands, ["[con_id=2] resize grow right 150 px"]);
+}
+
+#[test]
+fn skipped_subtree_is_left_alone() {
+    let rect = (0, 0, 1000, 1000);
+    let mut skipped = split(2, "splitv", (0, 0, 300, 1000), &[100, 900]);
+    skipped.marks.push("balance-skip".to_string());
+    let ws = node(1, "workspace", "splith", rect, vec![
+        skipped,
+        split(5, "splitv", (300, 0, 700, 1000), &[100, 900]),
+    ]);
+    let (mock, res) = balanced(ws, &Options::default());
+    res.unwrap();
+
+    // Resized along with its sibling, but not within
+    assert_eq!(sizes(mock.tree(), 1), [500, 500]);
+    assert_eq!(sizes(mock.tree(), 2), [100, 900]);
+    assert_eq!(sizes(mock.tree(), 5), [500, 500]);
+}
+
+#[test]
+fn marked_layout_only_applies_without_resizing() {
+    let mut ws = workspace(1, "splith", (0, 0, 1000, 800), &[300, 700]);
+    ws.marks.push("balance-layout=splitv".to_string());
+
+    // As a vertical split every window is already as high as the others
+    let mut mock = MockProvider::new(ws.clone());
+    assert!(plan(&mut mock, &ws, &Options::default()).unwrap().is_empty());
+    assert_eq!(imbalance(&ws, &Options::default())[0].layout, NodeLayout::SplitV);
+    assert!(diff(&ws, &ws, &Options::default()).unwrap().is_empty());
+
+    // Those that are run are not
+    assert_eq!(script(&ws, &ws, &Options::default()).unwrap(), [
+        "[con_id=2] resize set width 500 px",
+        "[con_id=3] resize set width 500 px",
+    ]);
+    let (mock, res) = balanced(ws, &Options::default());
+    res.unwrap();
+    assert_eq!(mock.commands, ["[con_id=2] resize grow right 200 px"]);
+}
+
+#[test]
+fn floating_focus_is_held_by_its_workspace() {
+    let mut ws = workspace(1, "splith", (0, 0, 1000, 800), &[500, 500]);
+    let mut floating = node(4, "floating_con", "none", (0, 0, 400, 300), vec![]);
+    floating.focused = true;
+    ws.floating_nodes.push(floating);
+    assert_eq!(top_focus(&ws).map(|n| n.id), Some(1));
+
+    ws.floating_nodes[0].focused = false;
+    ws.nodes[1].focused = true;
+    assert_eq!(top_focus(&ws).map(|n| n.id), Some(3));
+}
+
+/// What the workspace list says about the workspace node `ws`
+fn listed(ws: &Node, focused: bool) -> Workspace {
+    let Rect { x, y, width, height, .. } = ws.rect;
+    serde_json::from_value(json!({
+        "id": ws.id, "num": 1, "name": ws.name, "visible": true,
+        "focused": focused, "urgent": false,
+        "rect": { "x": x, "y": y, "width": width, "height": height },
+        "output": "OUT",
+    })).unwrap()
+}
+
+#[test]
+fn focus_is_found_in_the_tree_without_a_focused_workspace() {
+    let rect = (0, 0, 1000, 800);
+    let first = workspace(1, "splith", rect, &[500, 500]);
+    let mut second = workspace(4, "splith", rect, &[500, 500]);
+    second.nodes[0].focused = true;
+    let tree = node(0, "root", "splith", rect, vec![
+        node(10, "output", "output", rect, vec![first.clone(), second.clone()]),
+    ]);
+
+    let none_focused = [listed(&first, false), listed(&second, false)];
+    assert_eq!(focused_workspace(&tree, &none_focused).map(|n| n.id), Some(4));
+    // The list is still believed over the tree when it does say
+    let first_focused = [listed(&first, true), listed(&second, false)];
+    assert_eq!(focused_workspace(&tree, &first_focused).map(|n| n.id), Some(1));
+}
+
+#[test]
+fn constrained_split_converges() {
+    // The first window can only grow once the second has grown into the
+    // third, which takes another iteration
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[10, 10, 980]);
+    let (mock, res) = balanced(ws, &Options::default());
+
+    assert_eq!(res.unwrap().containers[0].iterations, 2);
+    assert_eq!(sizes(mock.tree(), 1), [334, 333, 333]);
+}
+
+#[test]
+fn percentages_round_but_take_a_single_batch() {
+    let rect = (0, 0, 1000, 900);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 900), &[100, 200, 600]),
+        leaf(6, (300, 0, 700, 900)),
+    ]);
+    let (px, px_res) = balanced(ws.clone(), &Options::default());
+    let ppt_opts = Options { ppt: true, ..Default::default() };
+    let (ppt, ppt_res) = balanced(ws, &ppt_opts);
+    let iterations = |res: Result<BalanceReport, AppError>| res.unwrap()
+        .containers.iter().map(|c| c.iterations).collect::<Vec<_>>();
+
+    // Pixels get there exactly, but the inner split has to go back for the
+    // window that could not grow into its neighbour at first
+    assert_eq!(sizes(px.tree(), 2), [300, 300, 300]);
+    assert_eq!(iterations(px_res), [1, 2]);
+    // A third is 33% of the split, which leaves the last window with the 
+    // rounding error, but it takes nothing to go back for
+    assert_eq!(sizes(ppt.tree(), 2), [297, 297, 306]);
+    assert_eq!(iterations(ppt_res), [1, 1]);
+    assert!(ppt.commands.iter().all(|c| c.ends_with(" ppt")));
+}
+
+#[test]
+fn absolute_sizes_take_more_commands_when_constrained() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[10, 10, 980]);
+    let (grown, grown_res) = balanced(ws.clone(), &Options::default());
+    let absolute = Options { absolute: true, ..Default::default() };
+    let (set, set_res) = balanced(ws, &absolute);
+
+    // Both get there, growing by turning a stuck resize around, setting by
+    // setting the sizes again once the third window has made room
+    assert_eq!(sizes(grown.tree(), 1), [334, 333, 333]);
+    assert_eq!(sizes(set.tree(), 1), [334, 333, 333]);
+    assert_eq!(grown_res.unwrap().containers[0].iterations, 2);
+    assert_eq!(set_res.unwrap().containers[0].iterations, 3);
+    assert_eq!(grown.commands, [
+        "[con_id=2] resize grow right 324 px",
+        "[con_id=3] resize grow right 647 px",
+        "[con_id=2] resize grow left 324 px",
+        "[con_id=2] resize grow right 324 px",
+    ]);
+    assert_eq!(set.commands, [
+        "[con_id=2] resize set width 334 px",
+        "[con_id=3] resize set width 333 px",
+        "[con_id=2] resize set width 334 px",
+        "[con_id=3] resize set width 333 px",
+    ]);
+}
+
+#[test]
+fn child_missing_between_iterations_fails_the_container() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[10, 10, 980]);
+    let mut mock = MockProvider::new(ws.clone());
+    mock.push_state(ws.clone());
+    // Closed while the first window was stuck
+    let mut closed = ws.clone();
+    closed.nodes.remove(1);
+    mock.push_state(closed);
+
+    let res = balance_with(&mut mock, &ws, &Options::default());
+    match res {
+        Err(AppError::Partial(failed)) => {
+            assert!(matches!(failed[..], [(1, AppError::NodeGone)]));
+        },
+        res => panic!("expected a window of container 1 to be gone, got {res:?}"),
+    }
+}
+
+#[test]
+fn depth_limit_leaves_grandchildren_alone() {
+    let rect = (0, 0, 1000, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        node(2, "con", "splitv", (0, 0, 500, 1000), vec![
+            split(3, "splith", (0, 0, 500, 300), &[100, 400]),
+            leaf(6, (0, 300, 500, 700)),
+        ]),
+        leaf(7, (500, 0, 500, 1000)),
+    ]);
+    let opts = Options { depth: Some(1), ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    assert_eq!(sizes(mock.tree(), 2), [500, 500]);
+    assert_eq!(sizes(mock.tree(), 3), [100, 400]);
+}
+
+#[test]
+fn marked_window_keeps_its_size() {
+    let mut ws = workspace(1, "splith", (0, 0, 1000, 800), &[200, 300, 500]);
+    ws.nodes[1].marks.push("pinned".to_string());
+    let opts = Options {
+        exclude_marks: HashSet::from(["pinned".to_string()]),
+        ..Default::default()
+    };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    // The edges on either side move together, so its size stays the same
+    assert_eq!(sizes(mock.tree(), 1), [350, 300, 350]);
+}
+
+#[test]
+fn equal_area_gives_every_window_as_much_space() {
+    let rect = (0, 0, 1200, 800);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        leaf(2, (0, 0, 600, 800)),
+        split(3, "splitv", (600, 0, 600, 800), &[200, 600]),
+    ]);
+    let opts = Options { equal_area: true, ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    let areas: Vec<i32> = bfs_collect(mock.tree(), |n| n.nodes.is_empty())
+        .iter().map(|n| n.rect.width * n.rect.height)
+        .collect();
+    assert_eq!(areas, [400 * 800; 3]);
+}
+
+#[test]
+fn containers_with_few_children_are_left_alone() {
+    let rect = (0, 0, 900, 1000);
+    let ws = node(1, "workspace", "splitv", rect, vec![
+        split(2, "splith", (0, 0, 900, 300), &[200, 700]),
+        split(5, "splith", (0, 300, 900, 700), &[100, 200, 600]),
+    ]);
+    let opts = Options { min_children: 3, ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    assert_eq!(sizes(mock.tree(), 1), [300, 700]);
+    assert_eq!(sizes(mock.tree(), 2), [200, 700]);
+    assert_eq!(sizes(mock.tree(), 5), [300, 300, 300]);
+}
+
+#[test]
+fn bottom_up_takes_as_many_commands() {
+    let rect = (0, 0, 1200, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        node(2, "con", "splitv", (0, 0, 700, 1000), vec![
+            split(3, "splith", (0, 0, 700, 300), &[100, 600]),
+            split(6, "splith", (0, 300, 700, 700), &[500, 150, 50]),
+        ]),
+        leaf(10, (700, 0, 500, 1000)),
+    ]);
+    let (top_down, res) = balanced(ws.clone(), &Options::default());
+    res.unwrap();
+    let opts = Options { bottom_up: true, ..Default::default() };
+    let (bottom_up, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    // Resizing a container keeps the proportions within it, so either way
+    // every edge out of place takes a single command
+    assert_eq!(top_down.commands.len(), 5);
+    assert_eq!(bottom_up.commands.len(), 5);
+    // Though the inner ones are left with what rounding those proportions
+    // leaves over
+    assert_eq!(sizes(top_down.tree(), 6), [200, 200, 200]);
+    assert_eq!(sizes(bottom_up.tree(), 6), [200, 199, 201]);
+}
+
+#[test]
+fn gaps_count_towards_the_percentages() {
+    // Four windows with 50px gaps between them, in 1000px
+    let ws = node(1, "workspace", "splith", (0, 0, 1000, 800), vec![
+        leaf(2, (0, 0, 100, 800)),
+        leaf(3, (150, 0, 200, 800)),
+        leaf(4, (400, 0, 250, 800)),
+        leaf(5, (700, 0, 300, 800)),
+    ]);
+    let percentages = |gaps| {
+        let opts = Options { ppt: true, gaps, ..Default::default() };
+        let mut mock = MockProvider::new(ws.clone());
+        plan(&mut mock, &ws, &opts).unwrap().iter()
+            .map(|op| op.ppt.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // A quarter of the 850px between the gaps is 21% of the container
+    assert_eq!(percentages(50), [21, 21, 21]);
+    assert_eq!(percentages(0), [25, 25, 25]);
+}
+
+#[test]
+fn sizes_snap_to_the_grid() {
+    let opts = Options { grid: 10, ..Default::default() };
+    let ws = workspace(1, "splith", (0, 0, 1000, 1000), &[100, 200, 700]);
+    let desired = targets(&ws.nodes, Axis::Horizontal, 1000, 0, 0, &opts);
+    // The last window takes up what rounding down the others left over
+    assert_eq!(desired.unwrap(), [330, 330, 340]);
+
+    for layout in ["splith", "splitv"] {
+        let ws = workspace(1, layout, (0, 0, 1000, 1000), &[100, 200, 700]);
+        let (mock, res) = balanced(ws, &opts);
+        res.unwrap();
+        assert_eq!(sizes(mock.tree(), 1), [330, 330, 340], "{layout}");
+    }
+}
+
+#[test]
+fn stuck_resize_is_retried_the_other_way() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 800), &[600, 300, 100]);
+    let mut mock = MockProvider::new(ws);
+    // The window after it has not got 150px to give
+    let ops = [ResizeOp {
+        con_id: 3,
+        layout: NodeLayout::SplitH,
+        change: "grow",
+        dir: "right",
+        px: 150,
+        ppt: None,
+    }];
+    let mut res = mock.run_command(&ops[0].command(Wm::Sway, 1000)).unwrap();
+    assert!(is_stuck(&res[0]));
+
+    let retried = resize_other_way(&mut mock, &ops, &mut res, Wm::Sway, 1000);
+    assert!(retried.unwrap());
+    assert!(res[0].is_ok());
+    assert_eq!(mock.commands[1], "[con_id=3] resize grow left 150 px");
+    assert_eq!(sizes(mock.tree(), 1), [450, 450, 100]);
+}
+
+#[test]
+fn device_pixels_are_converted_on_scaled_outputs() {
+    let opts = Options { tolerance: 5, min_width: 300, grid: 20,
+        ..Default::default() };
+    let logical = opts.in_logical_pixels(2.0);
+    assert_eq!(logical.tolerance, 3);
+    assert_eq!(logical.min_width, 150);
+    assert_eq!(logical.grid, 10);
+
+    let rect = (0, 0, 1000, 800);
+    let ws = workspace(1, "splith", rect, &[100, 200, 700]);
+    let mut output = node(10, "output", "output", rect, vec![ws]);
+    output.name = Some("OUT".to_string());
+    let tree = node(0, "root", "splith", rect, vec![output]);
+
+    let opts = Options {
+        grid: 20,
+        device_scales: HashMap::from([("OUT".to_string(), 2.0)]),
+        ..Default::default()
+    };
+    let (mock, res) = balanced(tree, &opts);
+    res.unwrap();
+    // A grid of 20 pixels on the screen is one of 10 for sway
+    assert_eq!(sizes(mock.tree(), 1), [330, 330, 340]);
+}
+
+#[test]
+fn only_containers_of_the_type_are_balanced() {
+    let rect = (0, 0, 1000, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 1000), &[100, 900]),
+        leaf(5, (300, 0, 700, 1000)),
+    ]);
+
+    let opts = Options { containers: Containers::Con, ..Default::default() };
+    let (mock, res) = balanced(ws.clone(), &opts);
+    res.unwrap();
+    assert_eq!(sizes(mock.tree(), 1), [300, 700]);
+    assert_eq!(sizes(mock.tree(), 2), [500, 500]);
+
+    let opts = Options { containers: Containers::Workspace, ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+    assert_eq!(sizes(mock.tree(), 1), [500, 500]);
+    assert_eq!(sizes(mock.tree(), 2), [100, 900]);
+}
+
+#[test]
+fn marked_container_is_balanced_by_itself() {
+    let rect = (0, 0, 1000, 1000);
+    let mut main = split(3, "splith", (0, 0, 300, 500), &[100, 200]);
+    main.marks.push("main".to_string());
+    let ws = node(1, "workspace", "splith", rect, vec![
+        node(2, "con", "splitv", (0, 0, 300, 1000), vec![
+            main,
+            leaf(6, (0, 500, 300, 500)),
+        ]),
+        leaf(7, (300, 0, 700, 1000)),
+    ]);
+
+    let marked = find_marked(&ws, "main").unwrap();
+    assert_eq!(marked.id, 3);
+    let mut mock = MockProvider::new(ws.clone());
+    balance(&mut mock, marked).unwrap();
+    assert_eq!(sizes(mock.tree(), 1), [300, 700]);
+    assert_eq!(sizes(mock.tree(), 3), [150, 150]);
+
+    assert!(matches!(find_marked(&ws, "other"), Err(AppError::MarkNotFound(m))
+        if m == "other"));
+}
+
+#[test]
+fn root_is_descended_into() {
+    let rect = (0, 0, 1000, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 1000), &[100, 900]),
+        leaf(5, (300, 0, 700, 1000)),
+    ]);
+    let mut scratch = workspace(20, "splith", rect, &[100, 900]);
+    scratch.name = Some("__i3_scratch".to_string());
+    let root = node(0, "root", "none", rect, vec![
+        node(10, "output", "output", rect, vec![ws]),
+        node(11, "output", "output", rect, vec![scratch]),
+    ]);
+    let (mock, res) = balanced(root, &Options::default());
+    res.unwrap();
+
+    assert_eq!(sizes(mock.tree(), 1), [500, 500]);
+    assert_eq!(sizes(mock.tree(), 2), [500, 500]);
+    assert_eq!(sizes(mock.tree(), 20), [100, 900]);
+}
+
+#[test]
+fn titles_weigh_windows() {
+    let mut ws = workspace(1, "splith", (0, 0, 1000, 800), &[500, 500]);
+    ws.nodes[0].name = Some("editor [w=3]".to_string());
+    ws.nodes[1].name = Some("shell".to_string());
+    let opts = Options {
+        title_weight: Some(regex::Regex::new(r"\[w=([0-9.]+)\]").unwrap()),
+        ..Default::default()
+    };
+    let (mock, res) = balanced(ws.clone(), &opts);
+    res.unwrap();
+    assert_eq!(sizes(mock.tree(), 1), [750, 250]);
+
+    // Without a group to take the weight from, every window weighs the same
+    let opts = Options {
+        title_weight: Some(regex::Regex::new(r"\[w=[0-9.]+\]").unwrap()),
+        ..Default::default()
+    };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+    assert!(mock.commands.is_empty());
+}
+
+#[test]
+fn no_descend_leaves_grandchildren_alone() {
+    let rect = (0, 0, 1000, 1000);
+    let ws = node(1, "workspace", "splith", rect, vec![
+        split(2, "splitv", (0, 0, 300, 1000), &[100, 900]),
+        split(5, "splitv", (300, 0, 700, 1000), &[100, 900]),
+    ]);
+    let opts = Options { depth: Some(0), ..Default::default() };
+    let (mock, res) = balanced(ws, &opts);
+    res.unwrap();
+
+    assert_eq!(sizes(mock.tree(), 1), [500, 500]);
+    let heights: Vec<i32> = [3, 4, 6, 7].iter()
+        .map(|id| find_by_id(mock.tree(), *id).unwrap().rect.height)
+        .collect();
+    assert_eq!(heights, [100, 900, 100, 900]);
+}
+
+#[test]
+fn children_without_a_size_are_fetched_again() {
+    let stale = workspace(1, "splith", (0, 0, 1000, 800), &[0, 1000]);
+    let (mock, res) = balanced(stale.clone(), &Options::default());
+    assert!(res.unwrap().containers.is_empty());
+    assert!(mock.commands.is_empty());
+
+    // Read in the middle of switching workspaces, and fine the next time
+    let mut mock = MockProvider::new(stale.clone());
+    mock.push_state(stale.clone());
+    mock.push_state(workspace(1, "splith", (0, 0, 1000, 800), &[300, 700]));
+    balance(&mut mock, &stale).unwrap();
+    assert_eq!(mock.commands, ["[con_id=2] resize grow right 200 px"]);
+}
+
+#[test]
+fn windows_without_a_height_keep_their_aspect() {
+    let ws = workspace(1, "splith", (0, 0, 1000, 0), &[300, 700]);
+    let mut mock = MockProvider::new(ws.clone());
+
+    assert_eq!(balance_aspect(&mut mock, &ws, &Options::default(), 16.0 / 9.0)
+        .unwrap(), 0);
+    assert!(mock.commands.is_empty());
+}
+
+#[test]
+fn pinned_shares_leave_the_sum_as_it_is() {
+    let shares = apportion_min(1001, &[1.0, 10.0, 10.0], 200).unwrap();
+
+    assert_eq!(shares, [200, 401, 400]);
+    assert_eq!(shares.iter().sum::<i32>(), 1001);
+}
+
+#[test]
+fn axes_pick_their_side() {
+    assert_eq!(Axis::of(NodeLayout::SplitH), Some(Axis::Horizontal));
+    assert_eq!(Axis::of(NodeLayout::SplitV), Some(Axis::Vertical));
+    assert_eq!(Axis::of(NodeLayout::Tabbed), None);
+
+    let window = leaf(1, (0, 0, 300, 200));
+    let opts = Options { min_width: 30, min_height: 20, ..Default::default() };
+    let h = Axis::Horizontal;
+    assert_eq!((h.dim(&window), h.name(), h.min(&opts)), (300, "width", 30));
+    assert_eq!((h.grow_dir(), h.shrink_dir()), ("right", "left"));
+    let v = Axis::Vertical;
+    assert_eq!((v.dim(&window), v.name(), v.min(&opts)), (200, "height", 20));
+    assert_eq!((v.grow_dir(), v.shrink_dir()), ("down", "up"));
+}
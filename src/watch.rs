@@ -0,0 +1,171 @@
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::iterator::Signals;
+use swayipc::{Event, EventType, WindowChange, WorkspaceChange};
+use sway_balance_workspace::{connect, output, AppError};
+
+
+/// How often to balance while events keep coming in
+pub struct Timing {
+    /// Events arriving within this long of each other are handled as one
+    pub debounce: Duration,
+    /// Never balance again sooner than this after the previous time
+    pub interval: Duration,
+}
+
+enum Msg {
+    /// Something happened that may have unbalanced the layout, named like
+    /// `window::new`
+    Changed(String),
+    /// Asked to balance right away, by SIGUSR1
+    Now,
+    /// Time to go, either because we were told to or because sway went away
+    Stop,
+}
+
+
+/// What happened, if the event may have changed the tiling layout
+fn trigger(event: &Event) -> Option<String> {
+    let relevant = match event {
+        Event::Window(e) => matches!(e.change, 
+            WindowChange::New | WindowChange::Close 
+            | WindowChange::Move | WindowChange::Floating),
+        Event::Workspace(e) => matches!(e.change, WorkspaceChange::Move),
+        _ => false,
+    };
+    let name = match event {
+        Event::Window(e) => format!("window::{:?}", e.change),
+        Event::Workspace(e) => format!("workspace::{:?}", e.change),
+        _ => return None,
+    };
+    relevant.then(|| name.to_lowercase())
+}
+
+/// Forward the relevant sway events to `tx`
+fn listen_events(socket: Option<&Path>, tx: mpsc::Sender<Msg>) 
+-> Result<(), AppError> {
+    let events = connect(socket)?
+        .subscribe([EventType::Window, EventType::Workspace])
+        .map_err(|_| AppError::Subscribe)?;
+
+    thread::spawn(move || {
+        for event in events {
+            let msg = match event.as_ref().map(trigger) {
+                Ok(Some(trigger)) => Msg::Changed(trigger),
+                Ok(None) => continue,
+                Err(_) => Msg::Stop, // Most likely sway exited
+            };
+            let stop = matches!(msg, Msg::Stop);
+            if tx.send(msg).is_err() || stop { return }
+        }
+    });
+
+    Ok(())
+}
+
+/// Forward SIGINT and SIGTERM to `tx` so we can stop in between balances,
+/// and SIGUSR1 to balance right away
+fn listen_signals(tx: mpsc::Sender<Msg>) -> Result<(), AppError> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGUSR1])
+        .map_err(|_| AppError::Signals)?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let msg = match signal {
+                SIGUSR1 => Msg::Now,
+                _ => Msg::Stop,
+            };
+            let stop = matches!(msg, Msg::Stop);
+            if tx.send(msg).is_err() || stop { return }
+        }
+    });
+
+    Ok(())
+}
+
+
+/// What to report as the trigger of a balance asked for by SIGUSR1
+const SIGNAL: &str = "signal::usr1";
+
+/// Take in every change arriving on `rx` after `trigger` until there has 
+/// been none for `quiet`, and it is at least `until`, or until told to 
+/// balance right away. Returns what to report as the trigger of the balance:
+/// `trigger` itself, or the signal if that cut the wait short. None if told
+/// to stop in the meantime.
+fn settle(rx: &mpsc::Receiver<Msg>, quiet: Duration, until: Instant, 
+    trigger: String) -> Option<String>
+{
+    loop {
+        let left = until.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(quiet.max(left)) {
+            Ok(Msg::Changed(_)) => continue,
+            Ok(Msg::Now) => return Some(SIGNAL.to_string()),
+            Err(RecvTimeoutError::Timeout) => return Some(trigger),
+            Ok(Msg::Stop) | Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+/// Call `run` whenever the layout may have changed, with the first event
+/// that did, and whenever SIGUSR1 comes in, with the signal, until stopped by
+/// a signal. 
+/// Failing runs are reported but do not stop the watch.
+pub fn watch(socket: Option<&Path>, timing: &Timing,
+    mut run: impl FnMut(&str) -> Result<(), AppError>) -> Result<(), AppError>
+{
+    let (tx, rx) = mpsc::channel();
+    listen_events(socket, tx.clone())?;
+    listen_signals(tx)?;
+
+    let mut last_run: Option<Instant> = None;
+
+    loop {
+        let trigger = match rx.recv() {
+            Ok(Msg::Changed(trigger)) => {
+                // Wait for the burst of events to die down, and for the 
+                // previous run to be long enough ago. Resizing a window by
+                // hand fires a lot of them, and balancing in between would
+                // fight the user.
+                let until = last_run
+                    .map_or_else(Instant::now, |t| t + timing.interval);
+                match settle(&rx, timing.debounce, until, trigger) {
+                    Some(trigger) => trigger,
+                    None => return Ok(()),
+                }
+            },
+            // Whoever asked knows what they are doing
+            Ok(Msg::Now) => SIGNAL.to_string(),
+            Ok(Msg::Stop) | Err(_) => return Ok(()),
+        };
+
+        last_run = Some(Instant::now());
+        if let Err(e) = run(&trigger) {
+            output::error(e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_during_a_burst_is_the_trigger() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(Msg::Changed("window::new".to_string())).unwrap();
+        tx.send(Msg::Now).unwrap();
+        tx.send(Msg::Changed("window::close".to_string())).unwrap();
+
+        let quiet = Duration::from_millis(10);
+        let trigger = settle(&rx, quiet, Instant::now(), "window::move".into());
+        assert_eq!(trigger.as_deref(), Some(SIGNAL));
+
+        // What is left of the burst has no signal in it
+        let trigger = settle(&rx, quiet, Instant::now(), "window::move".into());
+        assert_eq!(trigger.as_deref(), Some("window::move"));
+    }
+}